@@ -1,11 +1,30 @@
-use super::utils::{safe_copy, set_zero};
+#![allow(clippy::needless_return)]
+
+use super::buffer_ref::BufferRef;
+use super::crc32c::{Checksum, ChecksumGuard, VerifyError};
+#[cfg(target_os = "linux")]
+use super::fd::{device_block_size, pread_full};
+use super::foreign::ForeignBuffer;
+use super::frozen::FrozenBuffer;
+#[cfg(target_os = "linux")]
+use super::mmap::MmapBuffer;
+#[cfg(feature = "trace-alloc")]
+use super::trace;
+use super::utils::{copy_nt, non_zero_len, safe_copy, set_zero, set_zero_nt};
+use super::write_guard::WriteGuard;
 use libc::{c_void, free, malloc, posix_memalign};
 use nix::errno::Errno;
-use std::slice;
+#[cfg(target_os = "linux")]
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::slice::{self, SliceIndex};
 use std::{
     fmt,
-    ops::{Deref, DerefMut},
+    ops::{
+        Deref, DerefMut, Index, IndexMut, Range, RangeFrom, RangeFull, RangeInclusive, RangeTo,
+        RangeToInclusive,
+    },
     ptr::{NonNull, null_mut},
+    sync::Arc,
 };
 
 /// Buffer is a static type,  size and cap (max to i32). Memory footprint is only 16B.
@@ -22,7 +41,10 @@ pub struct Buffer {
     buf_ptr: NonNull<c_void>,
     /// the highest bit of `size` represents `owned`
     pub(crate) size: u32,
-    /// the highest bit of `cap` represents `mutable`
+    /// the highest bit of `cap` represents `mutable`.
+    ///
+    /// An owned buffer is always mutable, so for the owned buffer from posix_memalign() the bit is cleared,
+    /// and the low bits of the capacity (multiple of 512) keep log2 of the alignment.
     pub(crate) cap: u32,
 }
 
@@ -39,6 +61,38 @@ unsafe impl Sync for Buffer {}
 pub const MIN_ALIGN: u32 = 512;
 pub const MAX_BUFFER_SIZE: usize = 1 << 31;
 
+/// With feature `debug-poison` on debug build, newly allocated memory is filled with this byte,
+/// so that reading before writing gives obviously-wrong data.
+#[cfg(any(feature = "debug-poison", doc))]
+pub const POISON_BYTE: u8 = 0xAA;
+
+/// The growth factor of capacity, when appending to a full owned buffer,
+/// passed to [Buffer::reserve_with()].
+///
+/// There's no room in the 16B Buffer to keep a policy, the growing methods
+/// without a policy argument always use [GrowthPolicy::Double].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GrowthPolicy {
+    /// Grow by 2x, the default
+    #[default]
+    Double,
+    /// Grow by 1.5x, for memory-tight workload
+    OneAndHalf,
+}
+
+/// Hexdump 48 bytes from start, for diagnostics
+fn hex_context(s: &[u8], start: usize) -> String {
+    let end = (start + 48).min(s.len());
+    let mut out = format!("[{:#x}..{:#x}]", start, end);
+    for b in s.get(start..end).unwrap_or(&[]) {
+        out.push_str(&format!(" {:02x}", b));
+    }
+    out
+}
+
+/// The low bits of `cap` keeping log2 of the alignment, for owned buffer from posix_memalign()
+const ALIGN_SHIFT_MASK: u32 = MIN_ALIGN - 1;
+
 fn is_aligned(offset: usize, size: usize) -> bool {
     return (offset & (MIN_ALIGN as usize - 1) == 0) && (size & (MIN_ALIGN as usize - 1) == 0);
 }
@@ -49,15 +103,16 @@ impl Buffer {
     ///
     /// **NOTE**: Be aware that buffer allocated is not initialized.
     ///
-    /// `size`: must be larger than zero
+    /// `size`: must be larger than zero, and multiple of 512, otherwise return `EINVAL`
     #[inline]
     pub fn aligned(size: i32) -> Result<Buffer, Errno> {
-        let mut _buf = Self::_alloc(MIN_ALIGN, size)?;
         #[cfg(all(feature = "fail", feature = "rand"))]
         fail::fail_point!("alloc_buf", |_| {
-            rand_buffer(&mut _buf);
+            let mut _buf = Self::_alloc(MIN_ALIGN, size)?;
+            super::utils::rand_buffer(&mut _buf);
             return Ok(_buf);
         });
+        let mut _buf = Self::_alloc(MIN_ALIGN, size)?;
         return Ok(_buf);
     }
 
@@ -66,20 +121,46 @@ impl Buffer {
     ///
     /// **NOTE**: Be aware that buffer allocated is not initialized.
     ///
-    /// `size`: must be larger than zero
+    /// `size`: must be larger than zero, and multiple of align
     ///
-    /// `align`: normally 512 or 4096
+    /// `align`: normally 512 or 4096, must be power of two and multiple of 512
+    ///
+    /// Return `EINVAL` if the size or align is invalid.
     #[inline]
     pub fn aligned_by(size: i32, align: u32) -> Result<Buffer, Errno> {
-        let mut _buf = Self::_alloc(align, size)?;
+        // align 0 means malloc() for _alloc()
+        if align == 0 {
+            return Err(Errno::EINVAL);
+        }
         #[cfg(all(feature = "fail", feature = "rand"))]
         fail::fail_point!("alloc_buf", |_| {
-            rand_buffer(&mut _buf);
+            let mut _buf = Self::_alloc(align, size)?;
+            super::utils::rand_buffer(&mut _buf);
             return Ok(_buf);
         });
+        let mut _buf = Self::_alloc(align, size)?;
         return Ok(_buf);
     }
 
+    /// Allocate mutable and owned aligned buffer for O_DIRECT on the block device of `fd`,
+    /// aligned to the device's logical block size (see [device_block_size()](crate::device_block_size())),
+    /// with size rounded up to the block size.
+    ///
+    /// **NOTE**: Be aware that buffer allocated is not initialized.
+    ///
+    /// `size`: must be larger than zero
+    #[cfg(target_os = "linux")]
+    #[inline]
+    pub fn aligned_for(fd: RawFd, size: i32) -> Result<Buffer, Errno> {
+        assert!(size > 0);
+        let align = device_block_size(fd)?.max(MIN_ALIGN);
+        let size = (size as u64).div_ceil(align as u64) * align as u64;
+        if size > i32::MAX as u64 {
+            return Err(Errno::EINVAL);
+        }
+        Self::aligned_by(size as i32, align)
+    }
+
     /// Allocate mutable and owned non-aligned Buffer by malloc(),
     /// with size set to capacity.
     ///
@@ -88,15 +169,56 @@ impl Buffer {
     /// `size`: must be larger than zero
     #[inline]
     pub fn alloc(size: i32) -> Result<Buffer, Errno> {
-        let mut _buf = Self::_alloc(0, size)?;
         #[cfg(all(feature = "fail", feature = "rand"))]
         fail::fail_point!("alloc_buf", |_| {
-            rand_buffer(&mut _buf);
+            let mut _buf = Self::_alloc(0, size)?;
+            super::utils::rand_buffer(&mut _buf);
             return Ok(_buf);
         });
+        let mut _buf = Self::_alloc(0, size)?;
         return Ok(_buf);
     }
 
+    /// Allocate mutable and owned non-aligned Buffer by malloc(),
+    /// with `len()` set to zero, the same as `Vec::with_capacity()`.
+    ///
+    /// `cap`: must be larger than zero
+    #[inline]
+    pub fn with_capacity(cap: i32) -> Result<Buffer, Errno> {
+        let mut buf = Self::alloc(cap)?;
+        buf.set_len(0);
+        return Ok(buf);
+    }
+
+    /// Allocate mutable and owned aligned buffer by posix_memalign(),
+    /// with `len()` set to zero.
+    ///
+    /// `cap`: must be larger than zero, and multiple of 512
+    #[inline]
+    pub fn aligned_with_capacity(cap: i32) -> Result<Buffer, Errno> {
+        let mut buf = Self::aligned(cap)?;
+        buf.set_len(0);
+        return Ok(buf);
+    }
+
+    /// Allocate mutable and owned non-aligned Buffer by malloc() of `size + margin` bytes,
+    /// with `len()` set to `size`, and the margin reserved as spare capacity.
+    ///
+    /// `size`: must be larger than zero
+    ///
+    /// `margin`: must be larger than or equal to zero
+    #[inline]
+    pub fn alloc_with_margin(size: i32, margin: i32) -> Result<Buffer, Errno> {
+        assert!(size > 0);
+        assert!(margin >= 0);
+        let Some(total) = size.checked_add(margin) else {
+            return Err(Errno::EINVAL);
+        };
+        let mut buf = Self::alloc(total)?;
+        buf.set_len(size as usize);
+        return Ok(buf);
+    }
+
     /// Allocate a buffer.
     ///
     /// `size`: must be larger than zero
@@ -105,8 +227,14 @@ impl Buffer {
         assert!(size > 0);
         let mut ptr: *mut c_void = null_mut();
         if align > 0 {
-            debug_assert!((align & (MIN_ALIGN - 1)) == 0);
-            debug_assert!((size as u32 & (align - 1)) == 0);
+            // The contract of posix_memalign(), and is_aligned() needs a multiple of MIN_ALIGN
+            if !align.is_power_of_two()
+                || !(align as usize).is_multiple_of(std::mem::size_of::<*mut c_void>())
+                || !align.is_multiple_of(MIN_ALIGN)
+                || !(size as u32).is_multiple_of(align)
+            {
+                return Err(Errno::EINVAL);
+            }
             unsafe {
                 let res = posix_memalign(&mut ptr, align as libc::size_t, size as libc::size_t);
                 if res != 0 {
@@ -119,28 +247,185 @@ impl Buffer {
                 return Err(Errno::ENOMEM);
             }
         }
+        #[cfg(all(feature = "debug-poison", debug_assertions))]
+        unsafe {
+            libc::memset(ptr, POISON_BYTE as i32, size as libc::size_t);
+        }
+        #[cfg(feature = "trace-alloc")]
+        trace::on_alloc(ptr, size as usize, align);
         // owned == true
         let _size = size as u32 | MAX_BUFFER_SIZE as u32;
-        // mutable == true
-        let _cap = _size;
+        // mutable == true, implied by owned for aligned buffer
+        let _cap = if align > 0 { size as u32 | align.trailing_zeros() } else { _size };
         Ok(Self { buf_ptr: unsafe { NonNull::new_unchecked(ptr) }, size: _size, cap: _cap })
     }
 
+    /// Change the capacity of an owned buffer to `new_cap`, preserving the content within
+    /// min(len(), new_cap). Aligned buffer is kept at its [alloc_align()](Buffer::alloc_align()),
+    /// with capacity rounded up to the alignment.
+    ///
+    /// Return `EINVAL` if the buffer is not owned, `EOVERFLOW` if `new_cap` is too large.
+    #[inline]
+    fn _realloc(&mut self, new_cap: usize) -> Result<(), Errno> {
+        self._realloc_by(self.alloc_align(), new_cap)
+    }
+
+    /// The same as [_realloc()](Buffer::_realloc()), but move into an allocation aligned by `align`,
+    /// which must be 0 for a malloc buffer, or not less than the [alloc_align()](Buffer::alloc_align())
+    /// of an aligned buffer.
+    fn _realloc_by(&mut self, align: u32, new_cap: usize) -> Result<(), Errno> {
+        if !self.is_owned() || !self.is_mutable() {
+            return Err(Errno::EINVAL);
+        }
+        debug_assert!(align == 0 || align >= self.alloc_align());
+        let new_cap =
+            if align > 0 { new_cap.div_ceil(align as usize) * align as usize } else { new_cap };
+        if new_cap == 0 || new_cap >= MAX_BUFFER_SIZE {
+            return Err(Errno::EOVERFLOW);
+        }
+        let len = self.len().min(new_cap);
+        let ptr: *mut c_void;
+        if self.capacity() == 0 {
+            // Converted from an empty Vec, the pointer is dangling
+            ptr = Self::_alloc(0, new_cap as i32)?.leak_raw();
+        } else if align > 0 {
+            let new_buf = Self::_alloc(align, new_cap as i32)?;
+            ptr = new_buf.leak_raw();
+            #[cfg(feature = "trace-alloc")]
+            trace::on_free(self.buf_ptr.as_ptr(), self.capacity());
+            unsafe {
+                std::ptr::copy_nonoverlapping(self.get_raw(), ptr as *mut u8, len);
+                free(self.buf_ptr.as_ptr());
+            }
+        } else {
+            ptr = unsafe { libc::realloc(self.buf_ptr.as_ptr(), new_cap as libc::size_t) };
+            if ptr.is_null() {
+                return Err(Errno::ENOMEM);
+            }
+            #[cfg(feature = "trace-alloc")]
+            {
+                trace::on_free(self.buf_ptr.as_ptr(), self.capacity());
+                trace::on_alloc(ptr, new_cap, 0);
+            }
+            #[cfg(all(feature = "debug-poison", debug_assertions))]
+            if new_cap > self.capacity() {
+                let old_cap = self.capacity();
+                unsafe {
+                    libc::memset(
+                        ptr.add(old_cap),
+                        POISON_BYTE as i32,
+                        (new_cap - old_cap) as libc::size_t,
+                    );
+                }
+            }
+        }
+        self.buf_ptr = unsafe { NonNull::new_unchecked(ptr) };
+        self.size = len as u32 | MAX_BUFFER_SIZE as u32;
+        self.cap = if align > 0 {
+            new_cap as u32 | align.trailing_zeros()
+        } else {
+            new_cap as u32 | MAX_BUFFER_SIZE as u32
+        };
+        Ok(())
+    }
+
+    /// Round `cap` up to the multiple of [alloc_align()](Buffer::alloc_align()) for aligned buffer
+    #[inline]
+    fn round_cap(&self, cap: usize) -> usize {
+        match self.alloc_align() {
+            0 => cap,
+            align => cap.div_ceil(align as usize) * align as usize,
+        }
+    }
+
+    /// Ensure the capacity of an owned buffer is at least `min_cap`,
+    /// grow by doubling to make appends amortized O(1).
+    #[inline]
+    fn grow(&mut self, min_cap: usize) -> Result<(), Errno> {
+        self.grow_by(GrowthPolicy::Double, min_cap)
+    }
+
+    /// Ensure the capacity of an owned buffer is at least `min_cap`, grow by the factor of `policy`
+    fn grow_by(&mut self, policy: GrowthPolicy, min_cap: usize) -> Result<(), Errno> {
+        let cap = self.capacity();
+        if min_cap <= cap {
+            return Ok(());
+        }
+        let new_cap = match policy {
+            GrowthPolicy::Double => cap * 2,
+            GrowthPolicy::OneAndHalf => cap + cap / 2,
+        };
+        let new_cap = new_cap.clamp(8, MAX_BUFFER_SIZE - 1).max(min_cap);
+        self._realloc(new_cap)
+    }
+
+    /// Give up the ownership of the pointer, return it without freeing
+    #[inline]
+    fn leak_raw(self) -> *mut c_void {
+        let ptr = self.buf_ptr.as_ptr();
+        std::mem::forget(self);
+        ptr
+    }
+
     /// Wrap a mutable buffer passed from c code, without owner ship.
     ///
     /// **NOTE**: will not free on drop. You have to ensure the buffer valid throughout the lifecycle.
     ///
     /// `size`: must be larger than or equal to zero.
     #[inline]
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
     pub fn from_c_ref_mut(ptr: *mut c_void, size: i32) -> Self {
-        assert!(size >= 0);
+        assert!(size >= 0, "size {} must be >= 0", size);
         assert!(!ptr.is_null());
+        #[cfg(feature = "trace-alloc")]
+        trace::on_wrap(ptr, size as usize, true);
         // owned == false
         // mutable == true
         let _cap = size as u32 | MAX_BUFFER_SIZE as u32;
         Self { buf_ptr: unsafe { NonNull::new_unchecked(ptr) }, size: size as u32, cap: _cap }
     }
 
+    /// Adopt a buffer allocated by c code, with owner ship, which will be freed on drop.
+    /// The owned counterpart of [from_c_ref_mut()](Buffer::from_c_ref_mut()).
+    ///
+    /// `size`: the `len()`, must be larger than or equal to zero, and <= `cap`.
+    ///
+    /// `cap`: the size allocated, must be larger than zero.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be returned by malloc() / posix_memalign() of the same libc allocator,
+    /// not an interior pointer, not from a foreign allocator, and not freed elsewhere.
+    #[inline]
+    pub unsafe fn from_malloc_raw(ptr: *mut c_void, size: i32, cap: i32) -> Self {
+        assert!(size >= 0 && size <= cap, "size {} out of capacity {}", size, cap);
+        assert!(!ptr.is_null());
+        #[cfg(feature = "trace-alloc")]
+        trace::on_alloc(ptr, cap as usize, 0);
+        // owned == true
+        let _size = size as u32 | MAX_BUFFER_SIZE as u32;
+        // mutable == true
+        let _cap = cap as u32 | MAX_BUFFER_SIZE as u32;
+        Self { buf_ptr: unsafe { NonNull::new_unchecked(ptr) }, size: _size, cap: _cap }
+    }
+
+    /// Wrap a mutable buffer passed from c code, which must be released by `dropper`
+    /// (an arena allocator, GPU-pinned memory, etc) instead of free().
+    ///
+    /// The [ForeignBuffer] returned calls `dropper` exactly once, on drop.
+    ///
+    /// `size`: must be larger than or equal to zero.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for read and write of `size` bytes until `dropper` is called.
+    #[inline]
+    pub unsafe fn from_raw_with_dropper(
+        ptr: *mut c_void, size: i32, dropper: extern "C" fn(*mut c_void),
+    ) -> ForeignBuffer {
+        unsafe { ForeignBuffer::new(ptr, size, dropper) }
+    }
+
     /// Wrap a const buffer passed from c code, without owner ship.
     ///
     /// **NOTE**: will not free on drop. You have to ensure the buffer valid throughout the lifecycle
@@ -148,8 +433,10 @@ impl Buffer {
     /// `size`: must be larger than or equal to zero.
     #[inline]
     pub fn from_c_ref_const(ptr: *const c_void, size: i32) -> Self {
-        assert!(size >= 0);
+        assert!(size >= 0, "size {} must be >= 0", size);
         assert!(!ptr.is_null());
+        #[cfg(feature = "trace-alloc")]
+        trace::on_wrap(ptr, size as usize, false);
         // owned == false
         // mutable == false
         Self {
@@ -159,6 +446,47 @@ impl Buffer {
         }
     }
 
+    /// Wrap a mutable slice (e.g. a stack array), without owner ship.
+    /// For the lifetime tracked at compile time, use [BufferMut](crate::BufferMut) instead.
+    ///
+    /// **NOTE**: will not free on drop.
+    ///
+    /// # Safety
+    ///
+    /// The lifetime is not tracked, the slice must outlive the Buffer,
+    /// and not be accessed through other references meanwhile.
+    ///
+    /// # Panic
+    ///
+    /// If slice length >= MAX_BUFFER_SIZE
+    #[inline]
+    pub unsafe fn from_slice_mut(s: &mut [u8]) -> Self {
+        let len = s.len();
+        assert!(len < MAX_BUFFER_SIZE, "size {} >= {} is not supported", len, MAX_BUFFER_SIZE);
+        Self::from_c_ref_mut(s.as_mut_ptr() as *mut c_void, len as i32)
+    }
+
+    /// Wrap a const slice (e.g. a stack array), without owner ship, the buffer is not mutable.
+    /// For the lifetime tracked at compile time, use [BufferRef] instead.
+    ///
+    /// **NOTE**: will not free on drop.
+    ///
+    /// # Safety
+    ///
+    /// The lifetime is not tracked, the slice must outlive the Buffer.
+    /// The buffer must not be written through [as_mut()](Buffer::as_mut()) or `IndexMut`,
+    /// which is only checked on debug mode.
+    ///
+    /// # Panic
+    ///
+    /// If slice length >= MAX_BUFFER_SIZE
+    #[inline]
+    pub unsafe fn from_slice_ref(s: &[u8]) -> Self {
+        let len = s.len();
+        assert!(len < MAX_BUFFER_SIZE, "size {} >= {} is not supported", len, MAX_BUFFER_SIZE);
+        Self::from_c_ref_const(s.as_ptr() as *const c_void, len as i32)
+    }
+
     /// Tell whether the Buffer has true 'static lifetime.
     #[inline(always)]
     pub fn is_owned(&self) -> bool {
@@ -168,11 +496,25 @@ impl Buffer {
     /// Tell whether the Buffer can as_mut().
     #[inline(always)]
     pub fn is_mutable(&self) -> bool {
-        self.cap & (MAX_BUFFER_SIZE as u32) != 0
+        self.cap & (MAX_BUFFER_SIZE as u32) != 0 || self.is_owned()
+    }
+
+    /// Return the alignment of an owned buffer allocated by [aligned()](Buffer::aligned())
+    /// or [aligned_by()](Buffer::aligned_by()), which is kept on realloc.
+    ///
+    /// Return 0 for buffer from malloc() or `Vec<u8>`, and c ref, whatever the address is.
+    #[inline(always)]
+    pub fn alloc_align(&self) -> u32 {
+        if self.is_owned() && self.cap & (MAX_BUFFER_SIZE as u32) == 0 {
+            1 << (self.cap & ALIGN_SHIFT_MASK)
+        } else {
+            0
+        }
     }
 
     /// Return the buffer's size.
     #[inline(always)]
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         let size = self.size & (MAX_BUFFER_SIZE as u32 - 1);
         size as usize
@@ -181,20 +523,49 @@ impl Buffer {
     /// Return the memory capacity managed by buffer's ptr
     #[inline(always)]
     pub fn capacity(&self) -> usize {
-        let cap = self.cap & (MAX_BUFFER_SIZE as u32 - 1);
+        let mut cap = self.cap & (MAX_BUFFER_SIZE as u32 - 1);
+        if self.alloc_align() > 0 {
+            cap &= !ALIGN_SHIFT_MASK;
+        }
         cap as usize
     }
 
-    /// Change the buffer's size, the same as `Vec::set_len()`. Panics when len > capacity
+    /// Return the spare capacity beyond `len()`, which is `capacity() - len()`
+    #[inline(always)]
+    pub fn margin(&self) -> usize {
+        self.capacity() - self.len()
+    }
+
+    /// Return the usable size of the allocation by `malloc_usable_size()`, which may be larger
+    /// than `capacity()` due to the malloc size classes.
+    ///
+    /// Return `capacity()` for aligned buffer and c ref, or when not on linux.
+    #[inline]
+    pub fn usable_size(&self) -> usize {
+        #[cfg(target_os = "linux")]
+        if self.is_owned() && self.capacity() > 0 && self.alloc_align() == 0 {
+            return unsafe { libc::malloc_usable_size(self.buf_ptr.as_ptr()) };
+        }
+        self.capacity()
+    }
+
+    /// Change the buffer's size, the same as `Vec::set_len()`.
+    ///
+    /// # Panic
+    ///
+    /// When len >= MAX_BUFFER_SIZE, or len > capacity
     #[inline(always)]
     pub fn set_len(&mut self, len: usize) {
         assert!(len < MAX_BUFFER_SIZE, "size {} >= {} is not supported", len, MAX_BUFFER_SIZE);
-        assert!(len <= self.cap as usize, "size {} must be <= {}", len, self.cap);
+        let cap = self.capacity();
+        assert!(len <= cap, "size {} must be <= {}", len, cap);
         let owned: u32 = self.size & MAX_BUFFER_SIZE as u32;
-        self.size = owned | len as u32;
+        // Mask the len so it can never flip the owned bit
+        self.size = owned | (len as u32 & (MAX_BUFFER_SIZE as u32 - 1));
     }
 
     #[inline(always)]
+    #[allow(clippy::should_implement_trait)]
     pub fn as_ref(&self) -> &[u8] {
         unsafe { slice::from_raw_parts(self.buf_ptr.as_ptr() as *const u8, self.len()) }
     }
@@ -203,6 +574,7 @@ impl Buffer {
     ///
     /// On release will skip the check for speed.
     #[inline(always)]
+    #[allow(clippy::should_implement_trait)]
     pub fn as_mut(&mut self) -> &mut [u8] {
         #[cfg(debug_assertions)]
         {
@@ -213,135 +585,1417 @@ impl Buffer {
         unsafe { slice::from_raw_parts_mut(self.buf_ptr.as_ptr() as *mut u8, self.len()) }
     }
 
-    /// Check this buffer usable by aio. True when get from `Buffer::aligned()`.
-    #[inline(always)]
-    pub fn is_aligned(&self) -> bool {
-        is_aligned(self.buf_ptr.as_ptr() as usize, self.capacity())
-    }
-
-    /// Get buffer raw pointer
+    /// Expose the whole `[0..capacity()]` region for writing, to fill a frame of known layout
+    /// without zeroing first. Call [commit_len()](Buffer::commit_len()) after writing.
+    ///
+    /// # Safety
+    ///
+    /// The bytes beyond `len()` may be uninitialized, must be written before read.
+    ///
+    /// On debug mode, will panic if the buffer is not mutable.
     #[inline]
-    pub fn get_raw(&self) -> *const u8 {
-        self.buf_ptr.as_ptr() as *const u8
+    pub unsafe fn as_capacity_mut(&mut self) -> &mut [u8] {
+        #[cfg(debug_assertions)]
+        {
+            if !self.is_mutable() {
+                panic!("Cannot change a mutable buffer")
+            }
+        }
+        unsafe { slice::from_raw_parts_mut(self.buf_ptr.as_ptr() as *mut u8, self.capacity()) }
     }
 
-    /// Get buffer raw mut pointer
+    /// Set the final `len()` after writing through [as_capacity_mut()](Buffer::as_capacity_mut()).
+    ///
+    /// # Panic
+    ///
+    /// When len > capacity
     #[inline]
-    pub fn get_raw_mut(&mut self) -> *mut u8 {
-        self.buf_ptr.as_ptr() as *mut u8
+    pub fn commit_len(&mut self, len: usize) {
+        self.set_len(len);
     }
 
-    /// Copy from src u8 slice into self[offset..].
+    /// Convert `Vec<u8>` to an owned buffer aligned to `align`, for O_DIRECT.
     ///
-    /// **NOTE**: will not do memset.
+    /// Reuse the Vec without copying if its address and capacity happen to be multiple of `align`,
+    /// otherwise copy into a new aligned buffer with capacity rounded up to `align`, keeping the len.
     ///
-    /// # Argument
+    /// `align`: must be power of two and multiple of 512
     ///
-    ///  * offset: Address of this buffer to start filling.
+    /// Return `EINVAL` if the align is invalid, `EOVERFLOW` if the rounded size is too large.
+    pub fn from_vec_aligned(vec: Vec<u8>, align: u32) -> Result<Buffer, Errno> {
+        if !align.is_power_of_two() || !align.is_multiple_of(MIN_ALIGN) {
+            return Err(Errno::EINVAL);
+        }
+        let align_size = align as usize;
+        if vec.capacity() > 0
+            && vec.capacity() < MAX_BUFFER_SIZE
+            && (vec.as_ptr() as usize).is_multiple_of(align_size)
+            && vec.capacity().is_multiple_of(align_size)
+        {
+            return Ok(Self::from(vec));
+        }
+        let size = vec.len().max(1).div_ceil(align_size) * align_size;
+        if size >= MAX_BUFFER_SIZE {
+            return Err(Errno::EOVERFLOW);
+        }
+        let mut buf = Self::aligned_by(size as i32, align)?;
+        buf.set_len(vec.len());
+        buf.as_mut().copy_from_slice(&vec);
+        Ok(buf)
+    }
+
+    /// Clone into a new owned buffer aligned to `align` regardless of the alignment of self,
+    /// with capacity rounded up to `align`, keeping `len()`.
     ///
-    /// # Panic
+    /// While clone() only keeps the [alloc_align()](Buffer::alloc_align()) of an aligned buffer,
+    /// this keeps any buffer valid for the O_DIRECT block size.
     ///
-    /// If offset >= self.len(), will panic
+    /// `align`: must be power of two and multiple of 512
+    ///
+    /// Return `EINVAL` if the align is invalid, `EOVERFLOW` if the rounded size is too large.
     #[inline]
-    pub fn copy_from(&mut self, offset: usize, src: &[u8]) {
-        let size = self.len();
-        let dst = self.as_mut();
-        if offset > 0 {
-            assert!(offset < size);
-            safe_copy(&mut dst[offset..], src);
-        } else {
-            safe_copy(dst, src);
+    pub fn clone_aligned(&self, align: u32) -> Result<Buffer, Errno> {
+        self.copy_aligned(self.capacity(), align)
+    }
+
+    /// Copy `len()` bytes into a new owned buffer aligned to `align` regardless of the source,
+    /// with capacity of `len()` rounded up to `align`, and the padding beyond `len()` zeroed.
+    /// For the fallback into the O_DIRECT path.
+    ///
+    /// `align`: must be power of two and multiple of 512
+    ///
+    /// Return `EINVAL` if the align is invalid, `EOVERFLOW` if the rounded size is too large.
+    pub fn to_aligned(&self, align: u32) -> Result<Buffer, Errno> {
+        let len = self.len();
+        let mut new_buf = self.copy_aligned(len, align)?;
+        set_zero(unsafe { &mut new_buf.as_capacity_mut()[len..] });
+        Ok(new_buf)
+    }
+
+    /// Copy `len()` bytes into a new buffer aligned to `align`, with capacity of `cap` rounded up to `align`.
+    fn copy_aligned(&self, cap: usize, align: u32) -> Result<Buffer, Errno> {
+        // align 0 is rejected by aligned_by()
+        let size = cap.max(1).next_multiple_of(align.max(1) as usize);
+        if size >= MAX_BUFFER_SIZE {
+            return Err(Errno::EOVERFLOW);
         }
+        let mut new_buf = Self::aligned_by(size as i32, align)?;
+        new_buf.set_len(self.len());
+        new_buf.as_mut().copy_from_slice(self.as_ref());
+        Ok(new_buf)
     }
 
-    /// Copy from another u8 slice into self[offset..], and memset the rest part.
+    /// Divide `[0..len()]` into two disjoint mutable slices at `mid`.
     ///
-    /// Argument:
+    /// # Panic
     ///
-    ///  * offset: Address of this buffer to start filling.
+    /// If mid > len(), or on debug mode the buffer is not mutable
     #[inline]
-    pub fn copy_and_clean(&mut self, offset: usize, other: &[u8]) {
-        let end: usize;
-        let size = self.len();
-        let dst = self.as_mut();
-        assert!(offset < size);
-        if offset > 0 {
-            set_zero(&mut dst[0..offset]);
-            end = offset + safe_copy(&mut dst[offset..], other);
-        } else {
-            end = safe_copy(dst, other);
-        }
-        if size > end {
-            set_zero(&mut dst[end..]);
-        }
+    pub fn split_at_mut(&mut self, mid: usize) -> (&mut [u8], &mut [u8]) {
+        let len = self.len();
+        assert!(mid <= len, "mid {} out of buffer size {}", mid, len);
+        self.as_mut().split_at_mut(mid)
     }
 
-    /// Fill this buffer with zero
+    /// Split a fixed-size header of `N` bytes from the body within `len()`,
+    /// return None if `len() < N`.
     #[inline]
-    pub fn zero(&mut self) {
-        set_zero(self);
+    pub fn split_header<const N: usize>(&self) -> Option<(&[u8; N], &[u8])> {
+        self.as_ref().split_first_chunk::<N>()
     }
 
-    /// Fill specified region of buffer[offset..(offset+len)] with zero
+    /// Split a mutable fixed-size header of `N` bytes from the body within `len()`,
+    /// return None if `len() < N`.
+    ///
+    /// On debug mode, will panic if the buffer is not mutable.
     #[inline]
-    pub fn set_zero(&mut self, offset: usize, len: usize) {
-        let _len = self.len();
-        let mut end = offset + len;
-        if end > _len {
-            end = _len;
-        }
-        let buf = self.as_mut();
-        if offset > 0 || end < _len {
-            set_zero(&mut buf[offset..end]);
-        } else {
-            set_zero(buf);
-        }
+    pub fn split_header_mut<const N: usize>(&mut self) -> Option<(&mut [u8; N], &mut [u8])> {
+        self.as_mut().split_first_chunk_mut::<N>()
     }
-}
 
-/// Allocates a new memory with the same size and clone the content.
-/// If original buffer is a c reference, will get a owned buffer after clone().
-impl Clone for Buffer {
-    fn clone(&self) -> Self {
-        let mut new_buf = if self.is_aligned() {
-            Self::aligned(self.capacity() as i32).unwrap()
-        } else {
-            Self::alloc(self.capacity() as i32).unwrap()
+    /// Hand the spare capacity `[len()..capacity()]` to `reader`, and advance `len()` by the count
+    /// it returns. For filling a buffer from any read-like source without a manual `set_len()`.
+    ///
+    /// **NOTE**: Like [alloc()](Buffer::alloc()), the spare bytes are not initialized.
+    ///
+    /// Return `InvalidInput` if the buffer is not mutable, or `reader` returns a count larger
+    /// than the spare capacity.
+    pub fn read_into_spare(
+        &mut self, mut reader: impl FnMut(&mut [u8]) -> std::io::Result<usize>,
+    ) -> std::io::Result<usize> {
+        if !self.is_mutable() {
+            return Err(Errno::EINVAL.into());
+        }
+        let len = self.len();
+        let spare = unsafe {
+            slice::from_raw_parts_mut((self.buf_ptr.as_ptr() as *mut u8).add(len), self.margin())
         };
-        if self.len() != self.capacity() {
-            new_buf.set_len(self.len());
+        let spare_len = spare.len();
+        let n = reader(spare)?;
+        if n > spare_len {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("read {} larger than spare capacity {}", n, spare_len),
+            ));
         }
-        safe_copy(new_buf.as_mut(), self.as_ref());
-        new_buf
+        self.set_len(len + n);
+        Ok(n)
     }
-}
 
-/// Automatically free on drop when buffer is owned
-impl Drop for Buffer {
-    fn drop(&mut self) {
-        if self.is_owned() {
-            unsafe {
-                free(self.buf_ptr.as_ptr());
+    /// Return a guard derefs to the spare capacity, which advances `len()` on drop by the count
+    /// reported with [WriteGuard::advance()].
+    ///
+    /// # Panic
+    ///
+    /// If the buffer is not mutable
+    #[inline]
+    pub fn write_guard(&mut self) -> WriteGuard<'_> {
+        WriteGuard::new(self)
+    }
+
+    /// Read from `reader` into the spare capacity until the buffer is full or EOF, advancing `len()`.
+    /// Return the total count read, `ErrorKind::Interrupted` is retried.
+    ///
+    /// On error, `len()` covers the bytes read before it.
+    pub fn fill_from<R: std::io::Read>(&mut self, reader: &mut R) -> std::io::Result<usize> {
+        let mut total = 0;
+        while self.margin() > 0 {
+            match self.read_into_spare(|spare| reader.read(spare)) {
+                Ok(0) => break,
+                Ok(n) => total += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
             }
         }
+        Ok(total)
     }
-}
 
-/// Convert a owned Buffer to `Vec<u8>`. Panic when buffer is a ref.
-impl Into<Vec<u8>> for Buffer {
-    fn into(mut self) -> Vec<u8> {
-        if !self.is_owned() {
-            panic!("buffer is c ref, not owned");
+    /// Allocate an owned buffer of `n` bytes, and fill it from `reader` by `read_exact()`.
+    ///
+    /// Return `UnexpectedEof` if the reader has less than `n` bytes.
+    pub fn from_reader_exact<R: std::io::Read>(
+        reader: &mut R, n: usize,
+    ) -> std::io::Result<Buffer> {
+        if n == 0 {
+            return Ok(Self::from(Vec::new()));
         }
-        // Change to not owned, to prevent drop()
+        if n >= MAX_BUFFER_SIZE {
+            return Err(Errno::EOVERFLOW.into());
+        }
+        let mut buf = Self::alloc(n as i32)?;
+        reader.read_exact(buf.as_mut())?;
+        Ok(buf)
+    }
+
+    /// Iterate over the bytes within `len()`
+    #[inline(always)]
+    pub fn iter(&self) -> slice::Iter<'_, u8> {
+        self.as_ref().iter()
+    }
+
+    /// Iterate over the mutable bytes within `len()`.
+    ///
+    /// On debug mode, will panic if the buffer is not mutable, the same as [as_mut()](Buffer::as_mut()).
+    #[inline(always)]
+    pub fn iter_mut(&mut self) -> slice::IterMut<'_, u8> {
+        self.as_mut().iter_mut()
+    }
+
+    /// Iterate over mutable chunks of `size` bytes within `len()`, the last one may be shorter.
+    /// For in-place transforms page by page.
+    ///
+    /// # Panic
+    ///
+    /// If size is zero, or on debug mode the buffer is not mutable
+    #[inline]
+    pub fn chunks_mut(&mut self, size: usize) -> slice::ChunksMut<'_, u8> {
+        self.as_mut().chunks_mut(size)
+    }
+
+    /// Check this buffer usable by aio. True when get from `Buffer::aligned()`.
+    #[inline(always)]
+    pub fn is_aligned(&self) -> bool {
+        is_aligned(self.buf_ptr.as_ptr() as usize, self.capacity())
+    }
+
+    /// Check the O_DIRECT preconditions for a device of `logical_block` size,
+    /// return the description of the first condition failed.
+    fn check_dma(&self, logical_block: u32) -> Result<(), String> {
+        if !logical_block.is_power_of_two() {
+            return Err(format!("logical_block {} is not power of two", logical_block));
+        }
+        let block = logical_block as usize;
+        let ptr = self.get_raw();
+        if !(ptr as usize).is_multiple_of(block) {
+            return Err(format!("address {:p} is not aligned to {}", ptr, block));
+        }
+        let len = self.len();
+        if len == 0 {
+            return Err("len is zero".to_string());
+        }
+        if !len.is_multiple_of(block) {
+            return Err(format!("len {} is not multiple of {}", len, block));
+        }
+        Ok(())
+    }
+
+    /// Whether the buffer can be submitted with O_DIRECT to a device of `logical_block` size:
+    /// the address is aligned to `logical_block`, and `len()` is a non-zero multiple of it.
+    #[inline]
+    pub fn is_dma_safe(&self, logical_block: u32) -> bool {
+        self.check_dma(logical_block).is_ok()
+    }
+
+    /// Assert the buffer [is_dma_safe()](Buffer::is_dma_safe()), instead of failing obscurely
+    /// with `EINVAL` at syscall time.
+    ///
+    /// # Panic
+    ///
+    /// With a message of the condition failed
+    #[inline]
+    pub fn assert_dma_safe(&self, logical_block: u32) {
+        if let Err(msg) = self.check_dma(logical_block) {
+            panic!("buffer not dma safe: {}", msg);
+        }
+    }
+
+    /// Return the largest `[start, end)` within `len()`, which address and length are both
+    /// multiple of `align`, or None if there's no such region.
+    ///
+    /// Useful to split an IO into an aligned fast path (O_DIRECT) and the unaligned head / tail.
+    ///
+    /// # Panic
+    ///
+    /// If align is zero
+    #[inline]
+    pub fn aligned_subrange(&self, align: usize) -> Option<(usize, usize)> {
+        assert!(align > 0, "align must be > 0");
+        let addr = self.buf_ptr.as_ptr() as usize;
+        let start = (align - addr % align) % align;
+        let len = self.len();
+        if start >= len {
+            return None;
+        }
+        let end = start + (len - start) / align * align;
+        if end == start {
+            return None;
+        }
+        Some((start, end))
+    }
+
+    /// Get buffer raw pointer
+    #[inline]
+    pub fn get_raw(&self) -> *const u8 {
+        self.buf_ptr.as_ptr() as *const u8
+    }
+
+    /// Get buffer raw mut pointer
+    #[inline]
+    pub fn get_raw_mut(&mut self) -> *mut u8 {
+        self.buf_ptr.as_ptr() as *mut u8
+    }
+
+    /// Return the `[begin, end)` raw pointers over `len()`, the same as `slice::as_ptr_range()`.
+    ///
+    /// For zero-length buffer, end equals to begin.
+    #[inline]
+    pub fn as_ptr_range(&self) -> Range<*const u8> {
+        self.as_ref().as_ptr_range()
+    }
+
+    /// Return the `[begin, end)` raw mut pointers over `len()`, the same as `slice::as_mut_ptr_range()`.
+    ///
+    /// On debug mode, will panic if the Buffer is not mutable.
+    #[inline]
+    pub fn as_mut_ptr_range(&mut self) -> Range<*mut u8> {
+        self.as_mut().as_mut_ptr_range()
+    }
+
+    /// Whether the `[ptr, ptr + len())` ranges of two buffers intersect, for
+    /// `debug_assert!(!a.overlaps(&b))` before copying between views that may alias.
+    ///
+    /// Empty buffers never overlap.
+    #[inline]
+    pub fn overlaps(&self, other: &Buffer) -> bool {
+        let (a, b) = (self.as_ptr_range(), other.as_ptr_range());
+        !a.is_empty() && !b.is_empty() && a.start < b.end && b.start < a.end
+    }
+
+    /// Return an `iovec` over `len()`, for readv/writev/aio submission.
+    #[inline]
+    pub fn as_iovec(&self) -> libc::iovec {
+        libc::iovec { iov_base: self.buf_ptr.as_ptr(), iov_len: self.len() as libc::size_t }
+    }
+
+    /// Return an `iovec` over `len()`, to be filled by readv/aio.
+    ///
+    /// On debug mode, will panic if the Buffer is not mutable.
+    #[inline]
+    pub fn as_iovec_mut(&mut self) -> libc::iovec {
+        let s = self.as_mut();
+        libc::iovec { iov_base: s.as_mut_ptr() as *mut c_void, iov_len: s.len() as libc::size_t }
+    }
+
+    /// Return an `iovec` over `len()` with the fixed buffer index, for io_uring
+    /// `IORING_OP_READ_FIXED` / `WRITE_FIXED`. See [BufferRegistry](crate::BufferRegistry).
+    #[inline]
+    pub fn as_iovec_registered(&self, buf_index: u16) -> (libc::iovec, u16) {
+        (self.as_iovec(), buf_index)
+    }
+
+    /// Build an `iovec` array over a group of buffers.
+    #[inline]
+    pub fn as_iovecs(bufs: &[Buffer]) -> Vec<libc::iovec> {
+        bufs.iter().map(|buf| buf.as_iovec()).collect()
+    }
+
+    /// Return `(get_raw() as u64, len() as u64)` for the `aio_buf` / `aio_nbytes` fields of a
+    /// libaio `iocb`.
+    ///
+    /// On debug mode, will panic if the Buffer is not aligned, which O_DIRECT requires.
+    #[inline]
+    pub fn as_iocb_fields(&self) -> (u64, u64) {
+        debug_assert!(self.is_aligned(), "buffer {:p} not aligned for aio", self.get_raw());
+        (self.get_raw() as u64, self.len() as u64)
+    }
+
+    /// Build an `iovec` array over a group of buffers, for vectored libaio (`IOCB_CMD_PREADV` / `PWRITEV`).
+    ///
+    /// On debug mode, will panic if any of the Buffers is not aligned.
+    #[inline]
+    pub fn aio_iovecs(bufs: &[Buffer]) -> Vec<libc::iovec> {
+        bufs.iter()
+            .map(|buf| {
+                debug_assert!(buf.is_aligned(), "buffer {:p} not aligned for aio", buf.get_raw());
+                buf.as_iovec()
+            })
+            .collect()
+    }
+
+    /// Copy from src u8 slice into self[offset..].
+    ///
+    /// **NOTE**: will not do memset.
+    ///
+    /// # Argument
+    ///
+    ///  * offset: Address of this buffer to start filling.
+    ///
+    /// # Panic
+    ///
+    /// If offset >= self.len(), will panic
+    #[inline]
+    pub fn copy_from(&mut self, offset: usize, src: &[u8]) {
+        let size = self.len();
+        let dst = self.as_mut();
+        if offset > 0 {
+            assert!(offset < size, "offset {} out of buffer size {}", offset, size);
+            safe_copy(&mut dst[offset..], src);
+        } else {
+            safe_copy(dst, src);
+        }
+    }
+
+    /// Copy from self[offset..] into dst u8 slice, return the bytes copied,
+    /// which is min(self.len() - offset, dst.len()).
+    ///
+    /// # Argument
+    ///
+    ///  * offset: Address of this buffer to start reading.
+    ///
+    /// # Panic
+    ///
+    /// If offset > self.len(), will panic
+    #[inline]
+    pub fn copy_to_slice(&self, offset: usize, dst: &mut [u8]) -> usize {
+        let size = self.len();
+        assert!(offset <= size, "offset {} out of buffer size {}", offset, size);
+        safe_copy(dst, &self.as_ref()[offset..])
+    }
+
+    /// Copy the bytes of `value` into self[offset..offset + size_of::<T>()],
+    /// no alignment is required for offset.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be plain old data (e.g. `#[repr(C)]` with integer fields), without padding bytes,
+    /// otherwise uninitialized padding is copied into the buffer.
+    ///
+    /// # Panic
+    ///
+    /// If the region is out of `len()`
+    #[inline]
+    pub unsafe fn write_struct<T: Copy>(&mut self, offset: usize, value: &T) {
+        let size = std::mem::size_of::<T>();
+        let len = self.len();
+        assert!(
+            offset.checked_add(size).is_some_and(|end| end <= len),
+            "struct of {} bytes at offset {} out of buffer size {}",
+            size,
+            offset,
+            len
+        );
+        unsafe {
+            std::ptr::write_unaligned(self.get_raw_mut().add(offset) as *mut T, *value);
+        }
+    }
+
+    /// Copy out a `T` from self[offset..offset + size_of::<T>()],
+    /// no alignment is required for offset.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be plain old data, for which any bit pattern is valid
+    /// (not `bool`, `char`, enum, reference or pointer to be dereferenced).
+    ///
+    /// # Panic
+    ///
+    /// If the region is out of `len()`
+    #[inline]
+    pub unsafe fn read_struct<T: Copy>(&self, offset: usize) -> T {
+        let size = std::mem::size_of::<T>();
+        let len = self.len();
+        assert!(
+            offset.checked_add(size).is_some_and(|end| end <= len),
+            "struct of {} bytes at offset {} out of buffer size {}",
+            size,
+            offset,
+            len
+        );
+        unsafe { std::ptr::read_unaligned(self.get_raw().add(offset) as *const T) }
+    }
+
+    /// Copy from another u8 slice into self[offset..], and memset the rest part.
+    ///
+    /// Argument:
+    ///
+    ///  * offset: Address of this buffer to start filling.
+    #[inline]
+    pub fn copy_and_clean(&mut self, offset: usize, other: &[u8]) {
+        self.copy_and_clean_ret(offset, other);
+    }
+
+    /// The same as [copy_and_clean()](Buffer::copy_and_clean()), and return `(copied, zeroed)`,
+    /// `zeroed` counts the bytes cleared both before and after the copy.
+    #[inline]
+    #[allow(clippy::needless_late_init)]
+    pub fn copy_and_clean_ret(&mut self, offset: usize, other: &[u8]) -> (usize, usize) {
+        let copied: usize;
+        let size = self.len();
+        let dst = self.as_mut();
+        assert!(offset < size, "offset {} out of buffer size {}", offset, size);
+        if offset > 0 {
+            set_zero(&mut dst[0..offset]);
+            copied = safe_copy(&mut dst[offset..], other);
+        } else {
+            copied = safe_copy(dst, other);
+        }
+        let end = offset + copied;
+        if size > end {
+            set_zero(&mut dst[end..]);
+        }
+        (copied, size - copied)
+    }
+
+    /// Fill this buffer with zero
+    #[inline]
+    pub fn zero(&mut self) {
+        set_zero(self);
+    }
+
+    /// Fill this buffer with zero by non-temporal stores, see [set_zero_nt()](crate::set_zero_nt()).
+    ///
+    /// Use for a large one-shot fill which will not be read soon, to avoid polluting the cache.
+    #[inline]
+    pub fn set_zero_nt(&mut self) {
+        set_zero_nt(self.as_mut());
+    }
+
+    /// The same as [copy_from()](Buffer::copy_from()) by non-temporal stores,
+    /// see [copy_nt()](crate::copy_nt()).
+    ///
+    /// # Panic
+    ///
+    /// If offset >= self.len(), will panic
+    #[inline]
+    pub fn copy_from_nt(&mut self, offset: usize, src: &[u8]) {
+        let size = self.len();
+        let dst = self.as_mut();
+        if offset > 0 {
+            assert!(offset < size, "offset {} out of buffer size {}", offset, size);
+            copy_nt(&mut dst[offset..], src);
+        } else {
+            copy_nt(dst, src);
+        }
+    }
+
+    /// Fill specified region of buffer[offset..(offset+len)] with zero,
+    /// the region is clamped to `len()`.
+    ///
+    /// # Panic
+    ///
+    /// If offset > self.len(), or offset + len overflows
+    #[inline]
+    pub fn set_zero(&mut self, offset: usize, len: usize) {
+        self.set_zero_ret(offset, len);
+    }
+
+    /// The same as [set_zero()](Buffer::set_zero()), and return the bytes cleared.
+    #[inline]
+    pub fn set_zero_ret(&mut self, offset: usize, len: usize) -> usize {
+        let _len = self.len();
+        assert!(offset <= _len, "offset {} out of buffer size {}", offset, _len);
+        let Some(mut end) = offset.checked_add(len) else {
+            panic!("offset {} + len {} overflows", offset, len);
+        };
+        if end > _len {
+            end = _len;
+        }
+        let buf = self.as_mut();
+        if offset > 0 || end < _len {
+            set_zero(&mut buf[offset..end]);
+        } else {
+            set_zero(buf);
+        }
+        end - offset
+    }
+
+    /// Shrink `len()` to exclude the trailing zero bytes (the padding of an aligned block),
+    /// to zero if the whole buffer is zero. Capacity is not changed.
+    #[inline]
+    pub fn trim_trailing_zeros(&mut self) {
+        let len = non_zero_len(self.as_ref());
+        self.set_len(len);
+    }
+
+    /// Append one byte to an owned buffer, growing capacity by doubling when full.
+    ///
+    /// Return `EINVAL` if the buffer is not owned or not mutable.
+    #[inline]
+    pub fn push(&mut self, byte: u8) -> Result<(), Errno> {
+        if !self.is_owned() || !self.is_mutable() {
+            return Err(Errno::EINVAL);
+        }
+        let len = self.len();
+        if len == self.capacity() {
+            self.grow(len + 1)?;
+        }
+        unsafe { *(self.buf_ptr.as_ptr() as *mut u8).add(len) = byte };
+        self.set_len(len + 1);
+        Ok(())
+    }
+
+    /// Write the planes packed into `out`, element by element:
+    /// `planes[0][0], planes[1][0], ..., planes[0][1], planes[1][1], ...`, and set `out.len()`.
+    ///
+    /// Return `EINVAL` if there's no plane, `element_size` is zero, the planes are not of the
+    /// same length in multiple of `element_size`, or `out` is not mutable or has not enough capacity.
+    pub fn interleave(
+        planes: &[&Buffer], out: &mut Buffer, element_size: usize,
+    ) -> Result<(), Errno> {
+        let plane_len = Self::check_planes(planes.iter().map(|p| p.len()), element_size)?;
+        let total = plane_len * planes.len();
+        if !out.is_mutable() || out.capacity() < total {
+            return Err(Errno::EINVAL);
+        }
+        out.set_len(total);
+        let stride = element_size * planes.len();
+        let dst = out.as_mut();
+        for (i, plane) in planes.iter().enumerate() {
+            for (j, element) in plane.chunks_exact(element_size).enumerate() {
+                let offset = j * stride + i * element_size;
+                dst[offset..offset + element_size].copy_from_slice(element);
+            }
+        }
+        Ok(())
+    }
+
+    /// The reverse of [interleave()](Buffer::interleave()), split the packed `src` into `planes`,
+    /// and set the len of each plane.
+    ///
+    /// Return `EINVAL` if there's no plane, `element_size` is zero, `src.len()` is not multiple of
+    /// `element_size * planes.len()`, or any plane is not mutable or has not enough capacity.
+    pub fn deinterleave(
+        src: &Buffer, planes: &mut [&mut Buffer], element_size: usize,
+    ) -> Result<(), Errno> {
+        let stride = element_size * planes.len();
+        if stride == 0 || !src.len().is_multiple_of(stride) {
+            return Err(Errno::EINVAL);
+        }
+        let plane_len = src.len() / planes.len();
+        for plane in planes.iter() {
+            if !plane.is_mutable() || plane.capacity() < plane_len {
+                return Err(Errno::EINVAL);
+            }
+        }
+        let src = src.as_ref();
+        for (i, plane) in planes.iter_mut().enumerate() {
+            plane.set_len(plane_len);
+            for (j, element) in plane.as_mut().chunks_exact_mut(element_size).enumerate() {
+                let offset = j * stride + i * element_size;
+                element.copy_from_slice(&src[offset..offset + element_size]);
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the common length of the planes, which must be multiple of `element_size`.
+    fn check_planes(
+        mut lens: impl Iterator<Item = usize>, element_size: usize,
+    ) -> Result<usize, Errno> {
+        let Some(plane_len) = lens.next() else {
+            return Err(Errno::EINVAL);
+        };
+        if element_size == 0 || !plane_len.is_multiple_of(element_size) {
+            return Err(Errno::EINVAL);
+        }
+        if lens.any(|len| len != plane_len) {
+            return Err(Errno::EINVAL);
+        }
+        Ok(plane_len)
+    }
+
+    /// Keep only the bytes for which `f` returns true, compacted toward the front in order,
+    /// and truncate `len()` to the count kept, like `Vec::retain()`.
+    ///
+    /// `f` is called once for each byte in order. Each run of kept bytes is moved by one memmove,
+    /// nothing is moved when all the bytes are kept.
+    ///
+    /// # Panic
+    ///
+    /// If the buffer is not mutable
+    pub fn retain<F: FnMut(u8) -> bool>(&mut self, mut f: F) {
+        assert!(self.is_mutable(), "buffer is not mutable");
+        let s = self.as_mut();
+        let len = s.len();
+        let mut kept = 0;
+        // The start of the current run of kept bytes
+        let mut run: Option<usize> = None;
+        for i in 0..=len {
+            if i < len && f(s[i]) {
+                run.get_or_insert(i);
+            } else if let Some(start) = run.take() {
+                // Only the bytes before the run are overwritten, which are already visited
+                if start != kept {
+                    s.copy_within(start..i, kept);
+                }
+                kept += i - start;
+            }
+        }
+        self.set_len(kept);
+    }
+
+    /// Collapse the consecutive duplicate bytes into one, compacted toward the front,
+    /// and truncate `len()`, like `Vec::dedup()`.
+    ///
+    /// # Panic
+    ///
+    /// If the buffer is not mutable
+    pub fn dedup(&mut self) {
+        let mut last = None;
+        self.retain(|b| last.replace(b) != Some(b));
+    }
+
+    /// Insert `src` at the front, shifting the content right and updating `len()`,
+    /// for the header written after the payload. Owned buffer grows by doubling when needed.
+    ///
+    /// **NOTE**: Always moves the content, there's no room in the 16B Buffer to keep a front offset.
+    ///
+    /// Return `EINVAL` if the buffer is not mutable, or a c ref has not enough capacity.
+    #[inline]
+    pub fn prepend(&mut self, src: &[u8]) -> Result<(), Errno> {
+        self.replace_range(0, 0, src)
+    }
+
+    /// Replace self[offset..offset + old_len] with `replacement` of possibly different length,
+    /// shifting the tail and updating `len()`. Owned buffer grows by doubling when needed.
+    ///
+    /// Return `EINVAL` if the range is out of `len()`, the buffer is not mutable,
+    /// or a c ref has not enough capacity.
+    pub fn replace_range(
+        &mut self, offset: usize, old_len: usize, replacement: &[u8],
+    ) -> Result<(), Errno> {
+        let len = self.len();
+        if !self.is_mutable() || offset.checked_add(old_len).is_none_or(|end| end > len) {
+            return Err(Errno::EINVAL);
+        }
+        let new_len = len - old_len + replacement.len();
+        if new_len >= MAX_BUFFER_SIZE {
+            return Err(Errno::EOVERFLOW);
+        }
+        if new_len > self.capacity() {
+            self.grow(new_len)?;
+        }
+        let tail = len - offset - old_len;
+        unsafe {
+            let ptr = self.buf_ptr.as_ptr() as *mut u8;
+            if old_len != replacement.len() {
+                std::ptr::copy(
+                    ptr.add(offset + old_len),
+                    ptr.add(offset + replacement.len()),
+                    tail,
+                );
+            }
+            std::ptr::copy_nonoverlapping(replacement.as_ptr(), ptr.add(offset), replacement.len());
+        }
+        self.set_len(new_len);
+        Ok(())
+    }
+
+    /// Ensure the capacity of an owned buffer is at least `len() + additional`, realloc to exactly that
+    /// without the overshoot of doubling. Aligned buffer is rounded up to, and kept at,
+    /// its [alloc_align()](Buffer::alloc_align()).
+    /// Does nothing if the capacity is already enough.
+    ///
+    /// Return `EINVAL` if the buffer is not owned or not mutable, `EOVERFLOW` if the size is too large,
+    /// `ENOMEM` when allocation failed.
+    pub fn try_reserve_exact(&mut self, additional: usize) -> Result<(), Errno> {
+        let Some(new_cap) = self.len().checked_add(additional) else {
+            return Err(Errno::EOVERFLOW);
+        };
+        if new_cap <= self.capacity() {
+            return Ok(());
+        }
+        self._realloc(new_cap)
+    }
+
+    /// Ensure the capacity of an owned buffer is at least `len() + additional`, growing by the
+    /// factor of `policy` for amortized O(1) appends. Does nothing if the capacity is already enough.
+    ///
+    /// Call it before [push()](Buffer::push()) and other growing methods, which then find
+    /// enough capacity, to append under a policy other than [GrowthPolicy::Double].
+    ///
+    /// Return `EINVAL` if the buffer is not owned or not mutable, `EOVERFLOW` if the size is too large,
+    /// `ENOMEM` when allocation failed.
+    pub fn reserve_with(&mut self, policy: GrowthPolicy, additional: usize) -> Result<(), Errno> {
+        let Some(min_cap) = self.len().checked_add(additional) else {
+            return Err(Errno::EOVERFLOW);
+        };
+        self.grow_by(policy, min_cap)
+    }
+
+    /// Make room for `len()` rounded up to the next multiple of `sector`, and zero-fill the padding
+    /// beyond `len()`, so that a short write can become a full-sector write. `len()` is not changed,
+    /// see [pad_len_to_sector()](Buffer::pad_len_to_sector()).
+    ///
+    /// Owned buffer grows when needed. Aligned buffer is kept aligned, by the larger one of its
+    /// [alloc_align()](Buffer::alloc_align()) and `sector` (when `sector` is a valid alignment).
+    ///
+    /// Return `EINVAL` if sector is zero, the buffer is not mutable, or a c ref has not enough capacity.
+    pub fn pad_to_sector(&mut self, sector: u32) -> Result<(), Errno> {
+        if sector == 0 || !self.is_mutable() {
+            return Err(Errno::EINVAL);
+        }
+        let len = self.len();
+        let Some(target) = len.checked_next_multiple_of(sector as usize) else {
+            return Err(Errno::EOVERFLOW);
+        };
+        if target > self.capacity() {
+            let align = match self.alloc_align() {
+                0 => 0,
+                align if sector.is_power_of_two() => align.max(sector),
+                align => align,
+            };
+            self._realloc_by(align, target)?;
+        }
+        unsafe {
+            libc::memset(self.buf_ptr.as_ptr().add(len), 0, target - len);
+        }
+        Ok(())
+    }
+
+    /// The same as [pad_to_sector()](Buffer::pad_to_sector()), and extend `len()` into the padding.
+    pub fn pad_len_to_sector(&mut self, sector: u32) -> Result<(), Errno> {
+        self.pad_to_sector(sector)?;
+        let len = self.len().next_multiple_of(sector as usize);
+        self.set_len(len);
+        Ok(())
+    }
+
+    /// Resize an owned buffer to `new_size`, the same as `Vec::resize(new_size, 0)`: preserve the content,
+    /// and zero the newly added `[old_len..new_size]`. Shrinking simply truncates `len()`.
+    ///
+    /// Return `EINVAL` if new_size is negative, or the buffer is not owned or not mutable.
+    pub fn resize_and_zero(&mut self, new_size: i32) -> Result<(), Errno> {
+        if new_size < 0 || !self.is_owned() || !self.is_mutable() {
+            return Err(Errno::EINVAL);
+        }
+        let new_size = new_size as usize;
+        let len = self.len();
+        if new_size > len {
+            self.grow(new_size)?;
+            unsafe {
+                libc::memset(self.buf_ptr.as_ptr().add(len), 0, new_size - len);
+            }
+        }
+        self.set_len(new_size);
+        Ok(())
+    }
+
+    /// Truncate `len()` to `n`, and realloc an owned buffer down to `n` bytes to release memory.
+    /// Aligned buffer is kept aligned, with capacity rounded up to its alignment.
+    ///
+    /// For c ref, only the length is adjusted.
+    ///
+    /// Does nothing to the content when `n >= len()`.
+    #[inline]
+    pub fn keep_prefix(&mut self, n: usize) -> Result<(), Errno> {
+        let n = n.min(self.len());
+        self.set_len(n);
+        if self.is_owned() && self.round_cap(n.max(1)) < self.capacity() {
+            self._realloc(n.max(1))?;
+        }
+        Ok(())
+    }
+
+    /// Realloc an owned buffer down to the capacity of `max(len(), target_cap)`, like `Vec::shrink_to()`.
+    /// Aligned buffer is kept aligned, with capacity rounded up to its alignment.
+    ///
+    /// Does nothing when `target_cap >= capacity()`.
+    ///
+    /// Return `EINVAL` if the buffer is not owned.
+    #[inline]
+    pub fn shrink_to(&mut self, target_cap: usize) -> Result<(), Errno> {
+        if !self.is_owned() {
+            return Err(Errno::EINVAL);
+        }
+        let new_cap = self.round_cap(target_cap.max(self.len()).max(1));
+        if new_cap >= self.capacity() {
+            return Ok(());
+        }
+        self._realloc(new_cap)
+    }
+
+    /// Return the index of the first occurrence of `needle` within `len()`, by libc memchr().
+    #[inline]
+    pub fn memchr(&self, needle: u8) -> Option<usize> {
+        let len = self.len();
+        let ptr = self.buf_ptr.as_ptr();
+        let res = unsafe { libc::memchr(ptr, needle as libc::c_int, len) };
+        if res.is_null() { None } else { Some(res as usize - ptr as usize) }
+    }
+
+    /// Return the index of the last occurrence of `needle` within `len()`, by libc memrchr().
+    #[inline]
+    pub fn memrchr(&self, needle: u8) -> Option<usize> {
+        #[cfg(target_os = "linux")]
+        {
+            let len = self.len();
+            let ptr = self.buf_ptr.as_ptr();
+            let res = unsafe { libc::memrchr(ptr, needle as libc::c_int, len) };
+            if res.is_null() { None } else { Some(res as usize - ptr as usize) }
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            self.as_ref().iter().rposition(|&c| c == needle)
+        }
+    }
+
+    /// Search for the first byte matching any of `needles` in a single pass,
+    /// return the position and the matched byte.
+    #[inline]
+    pub fn find_any(&self, needles: &[u8]) -> Option<(usize, u8)> {
+        let mut table = [false; 256];
+        for &c in needles {
+            table[c as usize] = true;
+        }
+        self.as_ref().iter().position(|&c| table[c as usize]).map(|i| (i, self.as_ref()[i]))
+    }
+
+    /// Count the occurrences of each byte value over `len()`.
+    pub fn byte_histogram(&self) -> [u64; 256] {
+        // Multiple accumulators to break the dependency on repeated bytes
+        let mut counts = [[0u64; 256]; 4];
+        let mut chunks = self.as_ref().chunks_exact(4);
+        for chunk in &mut chunks {
+            counts[0][chunk[0] as usize] += 1;
+            counts[1][chunk[1] as usize] += 1;
+            counts[2][chunk[2] as usize] += 1;
+            counts[3][chunk[3] as usize] += 1;
+        }
+        for &c in chunks.remainder() {
+            counts[0][c as usize] += 1;
+        }
+        let mut histogram = counts[0];
+        for i in 0..256 {
+            histogram[i] += counts[1][i] + counts[2][i] + counts[3][i];
+        }
+        histogram
+    }
+
+    /// Estimate the Shannon entropy of the content in bits per byte (0.0 ~ 8.0),
+    /// high entropy data is not worth compressing.
+    pub fn shannon_entropy(&self) -> f64 {
+        let len = self.len();
+        if len == 0 {
+            return 0.0;
+        }
+        let mut entropy = 0.0;
+        for count in self.byte_histogram() {
+            if count > 0 {
+                let p = count as f64 / len as f64;
+                entropy -= p * p.log2();
+            }
+        }
+        entropy
+    }
+
+    #[inline]
+    fn content(&self, trim_trailing_zeros: bool) -> &[u8] {
+        let s = self.as_ref();
+        if trim_trailing_zeros { &s[0..non_zero_len(s)] } else { s }
+    }
+
+    /// Hash the content of the buffer, for dedup purpose.
+    ///
+    /// The value is the xxHash64 (seed 0) of the content, stable across releases and platforms,
+    /// so it can be persisted as a content-addressed key.
+    ///
+    /// `trim_trailing_zeros`: when true, ignore the trailing zero bytes,
+    /// so buffers only differ in zero padding hash the same.
+    #[inline]
+    pub fn content_hash(&self, trim_trailing_zeros: bool) -> u64 {
+        super::xxhash::xxhash64(self.content(trim_trailing_zeros))
+    }
+
+    /// Compare the content of two buffers.
+    ///
+    /// `trim_trailing_zeros`: when true, ignore the trailing zero bytes of both buffers.
+    #[inline]
+    pub fn content_eq(&self, other: &Buffer, trim_trailing_zeros: bool) -> bool {
+        self.content(trim_trailing_zeros) == other.content(trim_trailing_zeros)
+    }
+
+    /// Return the xxHash64 of the content within `len()`, a fast hash for dedup keys.
+    /// Enabled with feature `xxhash`.
+    #[cfg(any(feature = "xxhash", doc))]
+    #[inline]
+    pub fn xxhash64(&self) -> u64 {
+        super::xxhash::xxhash64(self.as_ref())
+    }
+
+    /// Return the xxHash64 of `self[range]`. Enabled with feature `xxhash`.
+    ///
+    /// # Panic
+    ///
+    /// If range is out of `len()`
+    #[cfg(any(feature = "xxhash", doc))]
+    #[inline]
+    pub fn xxhash64_range(&self, range: Range<usize>) -> u64 {
+        super::xxhash::xxhash64(&self.as_ref()[range])
+    }
+
+    /// Capture the checksum `K` of the content, and verify it is not modified when the guard drops.
+    /// See [ChecksumGuard].
+    #[inline]
+    pub fn checksum_guard<K: Checksum>(&self) -> ChecksumGuard<'_, K> {
+        ChecksumGuard::new(self)
+    }
+
+    /// Iterate the content within `len()` in `chunk` sized pieces (the last one may be shorter),
+    /// call `per_chunk` on each, and fold them into one running checksum `K`.
+    /// Return the final checksum, fusing the checksum and IO loops in one pass.
+    ///
+    /// # Panic
+    ///
+    /// If chunk is zero
+    pub fn checksum_chunked<K: Checksum>(
+        &self, chunk: usize, mut per_chunk: impl FnMut(&[u8]),
+    ) -> u64 {
+        assert!(chunk > 0, "chunk must be > 0");
+        let mut k = K::new();
+        for data in self.as_ref().chunks(chunk) {
+            per_chunk(data);
+            k.update(data);
+        }
+        k.finalize()
+    }
+
+    /// Compare the content of two buffers within `len()`, for write-verify checks.
+    ///
+    /// Return `Err(offset)` of the first differing byte, or the shorter length when one
+    /// is a prefix of the other.
+    pub fn verify_equal(&self, other: &Buffer) -> Result<(), usize> {
+        let (a, b) = (self.as_ref(), other.as_ref());
+        let len = a.len().min(b.len());
+        let res =
+            unsafe { libc::memcmp(a.as_ptr() as *const c_void, b.as_ptr() as *const c_void, len) };
+        if res != 0 {
+            // Only scan byte by byte after memcmp finds a difference
+            let mut start = 0;
+            for (i, (x, y)) in a[..len].chunks(64).zip(b[..len].chunks(64)).enumerate() {
+                if x != y {
+                    start = i * 64;
+                    break;
+                }
+            }
+            let pos = a[start..len].iter().zip(&b[start..len]).position(|(x, y)| x != y);
+            return Err(start + pos.unwrap());
+        }
+        if a.len() != b.len() {
+            return Err(len);
+        }
+        Ok(())
+    }
+
+    /// Read back `len()` bytes of `fd` at `offset` into a temporary aligned buffer,
+    /// and compare with self. Used to verify after write.
+    ///
+    /// The region read is rounded out to the block size (the logical block size of a block device,
+    /// 4096 for a regular file), so `fd` can be opened with O_DIRECT.
+    ///
+    /// Return false if the file is shorter than expected.
+    #[cfg(target_os = "linux")]
+    pub fn equals_file_region(&self, fd: RawFd, offset: u64) -> Result<bool, Errno> {
+        let len = self.len();
+        if len == 0 {
+            return Ok(true);
+        }
+        let align = device_block_size(fd).unwrap_or(4096).max(MIN_ALIGN) as u64;
+        let start = offset - offset % align;
+        let head = (offset - start) as usize;
+        let size = (head + len).next_multiple_of(align as usize);
+        if size >= MAX_BUFFER_SIZE {
+            return Err(Errno::EINVAL);
+        }
+        let mut tmp = Self::aligned_by(size as i32, align as u32)?;
+        if pread_full(fd, tmp.as_mut(), start)? < head + len {
+            return Ok(false);
+        }
+        Ok(self.as_ref() == &tmp[head..head + len])
+    }
+
+    /// Read `data_len` bytes of `fd` at `offset` followed by a trailer of the checksum `K`
+    /// (`K::SIZE` bytes, little endian), and verify the data against it.
+    ///
+    /// On success `len()` is set to `data_len`. On error `len()` is set to 0, the unverified
+    /// data is never exposed as valid:
+    /// - `EINVAL` if self is not mutable, the capacity is less than `data_len + K::SIZE`,
+    ///   or `K::SIZE` is larger than 8
+    /// - `EIO` if the file ends before the trailer
+    /// - [VerifyError::ChecksumMismatch] with the computed and stored values
+    pub fn pread_verified<K: Checksum>(
+        &mut self, fd: RawFd, offset: u64, data_len: usize,
+    ) -> Result<(), VerifyError> {
+        self.set_len(0);
+        let total = data_len.saturating_add(K::SIZE);
+        if K::SIZE > 8 || !self.is_mutable() || total > self.capacity() {
+            return Err(Errno::EINVAL.into());
+        }
+        let buf = unsafe { &mut self.as_capacity_mut()[..total] };
+        if pread_full(fd, buf, offset)? < total {
+            return Err(Errno::EIO.into());
+        }
+        let mut stored = [0u8; 8];
+        stored[..K::SIZE].copy_from_slice(&buf[data_len..]);
+        let stored = u64::from_le_bytes(stored);
+        let computed = K::checksum(&buf[..data_len]);
+        if computed != stored {
+            return Err(VerifyError::ChecksumMismatch { computed, stored });
+        }
+        self.set_len(data_len);
+        Ok(())
+    }
+
+    /// Decode a frame of a `prefix_bytes` length prefix followed by the payload.
+    ///
+    /// Return `(payload_len, payload)`, where payload is a non-owned, immutable view
+    /// over `self[prefix_bytes..prefix_bytes + payload_len]` borrowing self, or None on a partial frame.
+    /// The payload is a [BufferRef] which has no `DerefMut`, it can not be written through.
+    ///
+    /// # Panic
+    ///
+    /// If prefix_bytes is not 1, 2, 4 or 8
+    pub fn parse_length_prefixed(
+        &self, prefix_bytes: usize, big_endian: bool,
+    ) -> Option<(usize, BufferRef<'_>)> {
+        assert!(
+            matches!(prefix_bytes, 1 | 2 | 4 | 8),
+            "prefix_bytes {} is not 1, 2, 4 or 8",
+            prefix_bytes
+        );
+        let data = self.as_ref();
+        let prefix = data.get(0..prefix_bytes)?;
+        let mut bytes = [0u8; 8];
+        if big_endian {
+            bytes[8 - prefix_bytes..].copy_from_slice(prefix);
+        } else {
+            bytes[..prefix_bytes].copy_from_slice(prefix);
+        }
+        let payload_len =
+            if big_endian { u64::from_be_bytes(bytes) } else { u64::from_le_bytes(bytes) };
+        let payload_len = usize::try_from(payload_len).ok()?;
+        let payload = data.get(prefix_bytes..prefix_bytes.checked_add(payload_len)?)?;
+        Some((payload_len, BufferRef::from_slice(payload)))
+    }
+
+    /// Copy `self[range]` into a new owned buffer (allocated by [alloc()](Buffer::alloc())),
+    /// which is independent of self.
+    ///
+    /// # Panic
+    ///
+    /// If range is out of `len()`
+    pub fn subbuffer_owned(&self, range: Range<usize>) -> Result<Buffer, Errno> {
+        let len = self.len();
+        assert!(
+            range.start <= range.end && range.end <= len,
+            "range {:?} out of buffer size {}",
+            range,
+            len
+        );
+        if range.is_empty() {
+            return Ok(Self::from(Vec::new()));
+        }
+        let mut buf = Self::alloc(range.len() as i32)?;
+        buf.as_mut().copy_from_slice(&self.as_ref()[range]);
+        Ok(buf)
+    }
+
+    /// Consume self, copy each `chunk` bytes into its own owned allocation, the last one may be shorter.
+    /// Chunks are aligned by [alloc_align()](Buffer::alloc_align()) (with capacity rounded up to it)
+    /// if self is an aligned buffer, the same as clone().
+    ///
+    /// # Panic
+    ///
+    /// If chunk is zero, or allocation failed
+    pub fn into_owned_chunks(self, chunk: usize) -> Vec<Buffer> {
+        assert!(chunk > 0, "chunk must be > 0");
+        let align = self.alloc_align();
+        self.as_ref()
+            .chunks(chunk)
+            .map(|part| {
+                let mut buf = if align > 0 {
+                    Self::aligned_by(self.round_cap(part.len()) as i32, align).unwrap()
+                } else {
+                    Self::alloc(part.len() as i32).unwrap()
+                };
+                buf.set_len(part.len());
+                buf.as_mut().copy_from_slice(part);
+                buf
+            })
+            .collect()
+    }
+
+    /// Return the offset of the first differing byte and (self's, other's) values,
+    /// or None if equal up to the shorter length.
+    #[inline]
+    pub fn diff(&self, other: &[u8]) -> Option<(usize, u8, u8)> {
+        let a = self.as_ref();
+        let i = a.iter().zip(other.iter()).position(|(x, y)| x != y)?;
+        Some((i, a[i], other[i]))
+    }
+
+    /// Test helper, panic with a hexdump around the first difference if the content
+    /// (including length) differs from `other`, rather than dumping the whole buffers.
+    #[track_caller]
+    pub fn assert_eq_bytes(&self, other: &[u8]) {
+        let a = self.as_ref();
+        let offset = match self.diff(other) {
+            Some((i, _, _)) => i,
+            None if a.len() == other.len() => return,
+            None => a.len().min(other.len()),
+        };
+        let start = offset.saturating_sub(16) & !15;
+        panic!(
+            "buffers differ at offset {} (len {} vs {})\n  left: {}\n right: {}",
+            offset,
+            a.len(),
+            other.len(),
+            hex_context(a, start),
+            hex_context(other, start)
+        );
+    }
+
+    /// Consume self and copy the content into an `Arc<[u8]>`, in one allocation.
+    ///
+    /// For the inverse without copying, see [from_arc_slice()](Buffer::from_arc_slice()).
+    #[inline]
+    pub fn into_arc_slice(self) -> Arc<[u8]> {
+        Arc::from(self.as_ref())
+    }
+
+    /// Wrap the content of an `Arc<[u8]>` as an immutable buffer view without copying,
+    /// the view keeps the Arc alive until dropped. The inverse of
+    /// [into_arc_slice()](Buffer::into_arc_slice()).
+    ///
+    /// # Panic
+    ///
+    /// If the length >= MAX_BUFFER_SIZE
+    #[inline]
+    pub fn from_arc_slice(arc: Arc<[u8]>) -> ForeignBuffer {
+        ForeignBuffer::from_arc(arc)
+    }
+
+    /// Split into `block_size` blocks (the last one may be shorter), compare the checksum `K`
+    /// of each block with `expected`, return the indices of the mismatching blocks,
+    /// to re-read only the corrupt ones. A block without an expected checksum counts as mismatching.
+    ///
+    /// # Panic
+    ///
+    /// If block_size is zero
+    pub fn locate_corruption<K: Checksum>(
+        &self, block_size: usize, expected: &[u64],
+    ) -> Vec<usize> {
+        assert!(block_size > 0, "block_size must be > 0");
+        self.as_ref()
+            .chunks(block_size)
+            .enumerate()
+            .filter(|(i, block)| expected.get(*i) != Some(&K::checksum(block)))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Convert an owned buffer with `len() == capacity()` into `Box<[u8]>` without copying,
+    /// otherwise return self, for the caller to copy or handle it.
+    #[inline]
+    pub fn try_into_boxed_slice(self) -> Result<Box<[u8]>, Buffer> {
+        if !self.is_owned() || self.len() != self.capacity() {
+            return Err(self);
+        }
+        let v: Vec<u8> = self.into();
+        Ok(v.into_boxed_slice())
+    }
+
+    /// Leak an owned mutable buffer into a `&'static mut [u8]` over `len()`, the same as `Vec::leak()`.
+    /// The memory is never freed.
+    ///
+    /// # Panic
+    ///
+    /// If the buffer is not owned or not mutable
+    #[inline]
+    pub fn leak(self) -> &'static mut [u8] {
+        assert!(self.is_owned(), "buffer is c ref, not owned");
+        assert!(self.is_mutable(), "buffer is not mutable");
+        let len = self.len();
+        let ptr = self.leak_raw();
+        unsafe { slice::from_raw_parts_mut(ptr as *mut u8, len) }
+    }
+
+    /// Consume self and copy the content into a new memfd (anonymous file) of `len()` bytes,
+    /// return the fd and a shared mapping of it, so the sender keeps access.
+    /// The fd can be passed to another process over a Unix socket, to mmap without copying.
+    ///
+    /// Return `EINVAL` if the buffer is empty or `name` contains NUL.
+    #[cfg(target_os = "linux")]
+    pub fn into_memfd(self, name: &str) -> Result<(OwnedFd, MmapBuffer), Errno> {
+        let len = self.len();
+        if len == 0 {
+            return Err(Errno::EINVAL);
+        }
+        let name = std::ffi::CString::new(name).map_err(|_| Errno::EINVAL)?;
+        let fd = unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(Errno::last());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        if unsafe { libc::ftruncate(fd.as_raw_fd(), len as libc::off_t) } < 0 {
+            return Err(Errno::last());
+        }
+        let mut map = MmapBuffer::map_shared(fd.as_raw_fd(), len, 0)?;
+        map.copy_from_slice(self.as_ref());
+        Ok((fd, map))
+    }
+
+    /// Open (or create) the file at `path`, resize it to `len`, and map it readable and writable
+    /// with MAP_SHARED, for in-place editing. Flush with [MmapBuffer::msync()], unmapped on drop.
+    ///
+    /// Return `EINVAL` if `len` is zero or too large.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated by anyone else (other process, or another mapping,
+    /// or write() on the same file) while the mapping is alive, which would change the content behind
+    /// the `&mut [u8]`, or raise SIGBUS on access beyond the new end of the file.
+    #[cfg(target_os = "linux")]
+    pub unsafe fn map_file_mut(path: &std::path::Path, len: usize) -> Result<MmapBuffer, Errno> {
+        if len == 0 || len >= MAX_BUFFER_SIZE {
+            return Err(Errno::EINVAL);
+        }
+        let to_errno = |e: std::io::Error| Errno::from_raw(e.raw_os_error().unwrap_or(libc::EIO));
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(to_errno)?;
+        file.set_len(len as u64).map_err(to_errno)?;
+        // The mapping stays valid after the file is closed
+        MmapBuffer::map_shared(file.as_raw_fd(), len, 0)
+    }
+
+    /// Finalize the buffer for sharing, the returned [FrozenBuffer] is read-only and cheap to clone.
+    #[inline]
+    pub fn freeze(self) -> FrozenBuffer {
+        FrozenBuffer::new(self)
+    }
+}
+
+/// Allocates a new memory with the same size and clone the content.
+/// If original buffer is a c reference, will get a owned buffer after clone().
+///
+/// A buffer from [Buffer::aligned()] or [Buffer::aligned_by()] is cloned with the same
+/// [alloc_align()](Buffer::alloc_align()). Any other buffer, including c reference and buffer
+/// from `Vec<u8>`, is always cloned by [Buffer::alloc()], regardless of where the pointer happens to sit,
+/// use [Buffer::clone_aligned()] if alignment is needed.
+impl Clone for Buffer {
+    fn clone(&self) -> Self {
+        if self.capacity() == 0 {
+            return Self::from(Vec::new());
+        }
+        let align = self.alloc_align();
+        let mut new_buf = if align > 0 {
+            Self::aligned_by(self.capacity() as i32, align).unwrap()
+        } else {
+            Self::alloc(self.capacity() as i32).unwrap()
+        };
+        if self.len() != self.capacity() {
+            new_buf.set_len(self.len());
+        }
+        safe_copy(new_buf.as_mut(), self.as_ref());
+        new_buf
+    }
+}
+
+/// Automatically free on drop when buffer is owned
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        // Converted from an empty Vec, the pointer is dangling and not allocated
+        if self.is_owned() && self.capacity() > 0 {
+            #[cfg(feature = "trace-alloc")]
+            trace::on_free(self.buf_ptr.as_ptr(), self.capacity());
+            unsafe {
+                free(self.buf_ptr.as_ptr());
+            }
+        }
+    }
+}
+
+/// Convert a owned Buffer to `Vec<u8>`. Panic when buffer is a ref.
+#[allow(clippy::from_over_into)]
+impl Into<Vec<u8>> for Buffer {
+    fn into(mut self) -> Vec<u8> {
+        if !self.is_owned() {
+            panic!("buffer is c ref, not owned");
+        }
+        #[cfg(feature = "trace-alloc")]
+        if self.capacity() > 0 {
+            trace::on_free(self.buf_ptr.as_ptr(), self.capacity());
+        }
+        let cap = self.capacity();
+        // Change to not owned, to prevent drop()
         self.size &= MAX_BUFFER_SIZE as u32 - 1;
         return unsafe {
-            Vec::<u8>::from_raw_parts(self.buf_ptr.as_ptr() as *mut u8, self.len(), self.capacity())
+            Vec::<u8>::from_raw_parts(self.buf_ptr.as_ptr() as *mut u8, self.len(), cap)
         };
     }
 }
 
 /// Convert `Vec<u8>` to Buffer, inherit the size and cap of Vec.
+///
+/// # Panic
+///
+/// If the len or capacity of Vec >= MAX_BUFFER_SIZE, which can not be stored without
+/// losing the high bit, and the capacity must round-trip back to the Vec exactly.
 impl From<Vec<u8>> for Buffer {
     fn from(buf: Vec<u8>) -> Self {
         let size = buf.len();
@@ -352,11 +2006,20 @@ impl From<Vec<u8>> for Buffer {
         let _size = size as u32 | MAX_BUFFER_SIZE as u32;
         // mutable == true
         let _cap = cap as u32 | MAX_BUFFER_SIZE as u32;
-        Buffer {
-            buf_ptr: unsafe { NonNull::new_unchecked(buf.leak().as_mut_ptr() as *mut c_void) },
-            size: _size,
-            cap: _cap,
+        let ptr = buf.leak().as_mut_ptr() as *mut c_void;
+        #[cfg(feature = "trace-alloc")]
+        if cap > 0 {
+            trace::on_alloc(ptr, cap, 0);
         }
+        Buffer { buf_ptr: unsafe { NonNull::new_unchecked(ptr) }, size: _size, cap: _cap }
+    }
+}
+
+/// Convert `Box<[u8]>` to Buffer without copying.
+impl From<Box<[u8]>> for Buffer {
+    #[inline]
+    fn from(buf: Box<[u8]>) -> Self {
+        Self::from(buf.into_vec())
     }
 }
 
@@ -376,6 +2039,13 @@ impl AsRef<[u8]> for Buffer {
     }
 }
 
+impl AsRef<Buffer> for Buffer {
+    #[inline]
+    fn as_ref(&self) -> &Buffer {
+        self
+    }
+}
+
 /// On debug mode, will panic if the Buffer is not owned [Buffer::from_c_ref_const()]
 ///
 /// On release will skip the check for speed.
@@ -392,3 +2062,66 @@ impl DerefMut for Buffer {
         self.as_mut()
     }
 }
+
+macro_rules! impl_index {
+    ($label: literal, $($t: ty),*) => {$(
+        impl Index<$t> for Buffer {
+            type Output = <$t as SliceIndex<[u8]>>::Output;
+
+            #[inline]
+            #[track_caller]
+            fn index(&self, idx: $t) -> &Self::Output {
+                let len = self.len();
+                match self.as_ref().get(idx.clone()) {
+                    Some(r) => r,
+                    None => panic!("{} {:?} out of bounds for buffer of length {}", $label, idx, len),
+                }
+            }
+        }
+
+        /// On debug mode, will panic if the buffer is not mutable.
+        impl IndexMut<$t> for Buffer {
+            #[inline]
+            #[track_caller]
+            fn index_mut(&mut self, idx: $t) -> &mut Self::Output {
+                let len = self.len();
+                match self.as_mut().get_mut(idx.clone()) {
+                    Some(r) => r,
+                    None => panic!("{} {:?} out of bounds for buffer of length {}", $label, idx, len),
+                }
+            }
+        }
+    )*};
+}
+
+// Indexing through Deref would give the slice's panic message without the buffer length
+impl_index!("index", usize);
+impl_index!(
+    "range",
+    Range<usize>,
+    RangeFrom<usize>,
+    RangeTo<usize>,
+    RangeFull,
+    RangeInclusive<usize>,
+    RangeToInclusive<usize>
+);
+
+impl<'a> IntoIterator for &'a Buffer {
+    type Item = &'a u8;
+    type IntoIter = slice::Iter<'a, u8>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut Buffer {
+    type Item = &'a mut u8;
+    type IntoIter = slice::IterMut<'a, u8>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
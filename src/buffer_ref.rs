@@ -0,0 +1,158 @@
+use crate::Buffer;
+use std::{
+    fmt,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+};
+
+/// A non-owned, immutable [Buffer] wrapping a const slice, with the lifetime tracked at compile time.
+/// For a mutable slice, see [BufferMut].
+///
+/// Prefer this over [Buffer::from_slice_ref()] / [Buffer::from_slice_mut()],
+/// keep the raw `from_c_ref_*` for true FFI.
+///
+/// ```
+/// use io_buffer::BufferRef;
+/// let data = vec![1u8; 10];
+/// let buf_ref = BufferRef::from_slice(&data);
+/// assert_eq!(buf_ref[0], 1);
+/// assert_eq!(buf_ref.as_buffer().len(), 10);
+/// ```
+///
+/// Can not outlive the source:
+///
+/// ```compile_fail
+/// use io_buffer::BufferRef;
+/// let buf_ref;
+/// {
+///     let data = vec![1u8; 10];
+///     buf_ref = BufferRef::from_slice(&data);
+/// }
+/// assert_eq!(buf_ref[0], 1);
+/// ```
+///
+/// Can not be written through:
+///
+/// ```compile_fail
+/// use io_buffer::BufferRef;
+/// let data = vec![1u8; 10];
+/// let mut buf_ref = BufferRef::from_slice(&data);
+/// buf_ref[0] = 2;
+/// ```
+pub struct BufferRef<'a> {
+    buf: Buffer,
+    _phan: PhantomData<&'a [u8]>,
+}
+
+impl fmt::Debug for BufferRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.buf.fmt(f)
+    }
+}
+
+impl<'a> BufferRef<'a> {
+    /// Wrap a const slice, the buffer is not mutable.
+    #[inline]
+    pub fn from_slice(s: &'a [u8]) -> Self {
+        // SAFETY: the slice is borrowed for 'a, outliving self
+        Self { buf: unsafe { Buffer::from_slice_ref(s) }, _phan: PhantomData }
+    }
+
+    /// Borrow as a [Buffer], for APIs that need one.
+    #[inline(always)]
+    pub fn as_buffer(&self) -> &Buffer {
+        &self.buf
+    }
+}
+
+impl Deref for BufferRef<'_> {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        self.buf.as_ref()
+    }
+}
+
+impl AsRef<[u8]> for BufferRef<'_> {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self.buf.as_ref()
+    }
+}
+
+impl AsRef<Buffer> for BufferRef<'_> {
+    #[inline]
+    fn as_ref(&self) -> &Buffer {
+        &self.buf
+    }
+}
+
+/// A non-owned, mutable [Buffer] wrapping a mutable slice, with the lifetime tracked
+/// at compile time. For a const slice, see [BufferRef].
+///
+/// ```
+/// use io_buffer::BufferMut;
+/// let mut data = vec![0u8; 10];
+/// {
+///     let mut buf_mut = BufferMut::from_slice(&mut data);
+///     buf_mut[0] = 1;
+///     assert_eq!(buf_mut.as_buffer().len(), 10);
+/// }
+/// assert_eq!(data[0], 1);
+/// ```
+pub struct BufferMut<'a> {
+    buf: Buffer,
+    _phan: PhantomData<&'a mut [u8]>,
+}
+
+impl fmt::Debug for BufferMut<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.buf.fmt(f)
+    }
+}
+
+impl<'a> BufferMut<'a> {
+    /// Wrap a mutable slice.
+    #[inline]
+    pub fn from_slice(s: &'a mut [u8]) -> Self {
+        // SAFETY: the slice is borrowed mutably for 'a, outliving self
+        Self { buf: unsafe { Buffer::from_slice_mut(s) }, _phan: PhantomData }
+    }
+
+    /// Borrow as a [Buffer], for APIs that need one.
+    #[inline(always)]
+    pub fn as_buffer(&self) -> &Buffer {
+        &self.buf
+    }
+}
+
+impl Deref for BufferMut<'_> {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        self.buf.as_ref()
+    }
+}
+
+impl DerefMut for BufferMut<'_> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.buf.as_mut()
+    }
+}
+
+impl AsRef<[u8]> for BufferMut<'_> {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self.buf.as_ref()
+    }
+}
+
+impl AsRef<Buffer> for BufferMut<'_> {
+    #[inline]
+    fn as_ref(&self) -> &Buffer {
+        &self.buf
+    }
+}
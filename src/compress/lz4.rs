@@ -1,11 +1,17 @@
 use super::Compression;
-use std::io::{Error, ErrorKind, Result};
+use std::ffi::CStr;
+use std::io::{Error, Result};
 
-pub const ERR_LZ4_COMPRESS: &'static str = "lz4_compress_failed";
-pub const ERR_LZ4_DECOMPRESS: &'static str = "lz4_decompress_failed";
+pub const ERR_LZ4_COMPRESS: &str = "lz4_compress_failed";
+pub const ERR_LZ4_DECOMPRESS: &str = "lz4_decompress_failed";
 
 pub struct LZ4();
 
+unsafe extern "C" {
+    // Not exported by lz4-sys, but linked in the same library
+    fn LZ4_versionString() -> *const libc::c_char;
+}
+
 impl Compression for LZ4 {
     #[inline]
     fn compress_bound(size: usize) -> usize {
@@ -23,7 +29,7 @@ impl Compression for LZ4 {
             )
         };
         if compressed_len <= 0 {
-            Err(Error::new(ErrorKind::Other, ERR_LZ4_COMPRESS))
+            Err(Error::other(ERR_LZ4_COMPRESS))
         } else {
             Ok(compressed_len as usize)
         }
@@ -39,15 +45,21 @@ impl Compression for LZ4 {
                 dest.len() as i32,
             )
         };
-        if decompressed_len <= 0 {
-            Err(Error::new(ErrorKind::Other, ERR_LZ4_DECOMPRESS))
+        if decompressed_len < 0 {
+            Err(Error::other(ERR_LZ4_DECOMPRESS))
         } else {
             Ok(decompressed_len as usize)
         }
     }
+
+    #[inline]
+    fn version() -> &'static str {
+        unsafe { CStr::from_ptr(LZ4_versionString()) }.to_str().unwrap_or("unknown")
+    }
 }
 
 #[cfg(test)]
+#[allow(clippy::unnecessary_cast, clippy::manual_div_ceil, clippy::unnecessary_mut_passed)]
 mod tests {
 
     use std::time::Instant;
@@ -58,7 +70,14 @@ mod tests {
         io::{Read, Write},
     };
 
-    use super::{super::Compression, LZ4};
+    use super::{
+        super::{
+            Compression, CompressionKind, Identity, backend_versions, compress_batch,
+            compress_best, compress_blocked, compress_into, decompress_batch, decompress_blocked,
+            decompress_capped, transcode, validate_codec,
+        },
+        LZ4,
+    };
 
     //use self::cpuprofiler::PROFILER;
 
@@ -87,6 +106,17 @@ mod tests {
         assert_eq!(&decompressed_buffer[0..decompressed_len as usize], &buffer[0..]);
     }
 
+    #[test]
+    fn test_decompress_empty() {
+        let mut compressed = Buffer::alloc(LZ4::compress_bound(0) as i32).unwrap();
+        let compressed_len = LZ4::compress(&[], &mut compressed).unwrap();
+        let mut dest = Buffer::alloc(16).unwrap();
+        assert_eq!(LZ4::decompress(&compressed[0..compressed_len], &mut dest).unwrap(), 0);
+        assert_eq!(LZ4::decompress(&compressed[0..compressed_len], &mut []).unwrap(), 0);
+        // Corrupted data is still an error
+        assert!(LZ4::decompress(&[0xff; 4], &mut dest).is_err());
+    }
+
     #[test]
     fn test_benchmark_compress() {
         let loop_cnt: u64 = 1000000;
@@ -149,6 +179,63 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_version() {
+        let version = LZ4::version();
+        println!("lz4 version {}", version);
+        let parts: Vec<u32> = version.split('.').map(|s| s.parse().unwrap()).collect();
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0], 1);
+        assert_eq!(backend_versions(), vec![("lz4", version)]);
+    }
+
+    #[test]
+    fn test_validate_codec() {
+        validate_codec::<LZ4>(200, 64 * 1024);
+    }
+
+    #[test]
+    fn test_decompress_capped() {
+        let mut buffer = Buffer::alloc(4096).unwrap();
+        rand_buffer(&mut buffer);
+        let mut compressed = Buffer::alloc(LZ4::compress_bound(4096) as i32).unwrap();
+        let compressed_len = LZ4::compress(&buffer, &mut compressed).unwrap();
+        compressed.set_len(compressed_len);
+        let decompressed = decompress_capped::<LZ4>(&compressed, 4096, 8192).unwrap();
+        assert_eq!(&decompressed[..], &buffer[..]);
+        let err = decompress_capped::<LZ4>(&compressed, usize::MAX, 8192).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        let err = decompress_capped::<LZ4>(&compressed, 8193, 8192).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        // claimed len too small for the frame
+        assert!(decompress_capped::<LZ4>(&compressed, 1024, 8192).is_err());
+    }
+
+    #[test]
+    fn test_compress_batch() {
+        let mut parts = Vec::new();
+        for i in 1..20 {
+            let mut part = Buffer::alloc(i * 100).unwrap();
+            rand_buffer(&mut part);
+            part.set_zero(0, (i * 50) as usize);
+            parts.push(part);
+        }
+        let srcs: Vec<&[u8]> = parts.iter().map(|p| p.as_ref()).collect();
+        let (compressed, index) = compress_batch::<LZ4>(&srcs).unwrap();
+        assert_eq!(index.len(), parts.len());
+        let mut offset = 0;
+        for (part_offset, part_len) in &index {
+            assert_eq!(*part_offset, offset);
+            offset += part_len;
+        }
+        assert_eq!(compressed.len(), offset as usize);
+        let decompressed = decompress_batch::<LZ4>(&compressed, &index, 4096).unwrap();
+        assert_eq!(decompressed.len(), parts.len());
+        for (i, part) in parts.iter().enumerate() {
+            assert_eq!(&decompressed[i][..], &part[..]);
+        }
+    }
+
     //#[test]
     #[allow(dead_code)]
     fn test_compatibility() {
@@ -192,4 +279,98 @@ mod tests {
         println!("decompressed_buffer size={}", decompressed_len);
         assert_eq!(&src_buffer[0..src_len], &decompressed_buffer[0..decompressed_len as usize]);
     }
+
+    #[test]
+    fn test_compress_into() {
+        let src = [7u8; 4096];
+        let mut dst = Buffer::alloc(LZ4::compress_bound(src.len()) as i32).unwrap();
+        let compressed_len = compress_into::<LZ4>(&src, &mut dst).unwrap();
+        assert!(compressed_len < 100);
+        assert_eq!(dst.len(), compressed_len);
+        let mut out = Buffer::alloc(4096).unwrap();
+        assert_eq!(LZ4::decompress(&dst, &mut out).unwrap(), 4096);
+        assert_eq!(&out[..], &src[..]);
+        let mut small = Buffer::alloc(8).unwrap();
+        small.set_len(3);
+        assert!(compress_into::<LZ4>(&src, &mut small).is_err());
+        assert_eq!(small.len(), 3);
+    }
+
+    #[test]
+    fn test_compress_best() {
+        let kinds = [CompressionKind::None, CompressionKind::Lz4];
+        let src = [7u8; 4096];
+        let (kind, compressed) = compress_best(&src, &kinds).unwrap();
+        assert_eq!(kind, CompressionKind::Lz4);
+        assert!(compressed.len() < 100);
+        let mut out = Buffer::alloc(4096).unwrap();
+        assert_eq!(kind.decompress(&compressed, &mut out).unwrap(), 4096);
+        assert_eq!(&out[..], &src[..]);
+        // Not compressible
+        let mut src = Buffer::alloc(4096).unwrap();
+        fastrand::fill(&mut src);
+        let (kind, stored) = compress_best(&src, &kinds).unwrap();
+        assert_eq!(kind, CompressionKind::None);
+        assert_eq!(&stored[..], &src[..]);
+    }
+
+    #[test]
+    fn test_transcode() {
+        let mut src = Buffer::alloc(10000).unwrap();
+        rand_buffer(&mut src);
+        let mut compressed = Buffer::alloc(LZ4::compress_bound(src.len()) as i32).unwrap();
+        compress_into::<LZ4>(&src, &mut compressed).unwrap();
+        let stored = transcode::<LZ4, Identity>(&compressed, src.len()).unwrap();
+        assert_eq!(&stored[..], &src[..]);
+        let back = transcode::<Identity, LZ4>(&stored, src.len()).unwrap();
+        let mut out = Buffer::alloc(src.len() as i32).unwrap();
+        assert_eq!(LZ4::decompress(&back, &mut out).unwrap(), src.len());
+        assert_eq!(&out[..], &src[..]);
+        // Wrong orig_len
+        let e = transcode::<Identity, LZ4>(&stored[0..100], src.len()).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        assert!(transcode::<LZ4, Identity>(&compressed, src.len() - 1).is_err());
+        // LZ4::compress_bound() truncates the size to i32
+        let e = transcode::<Identity, LZ4>(&stored, (1 << 32) + 10).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_compress_blocked() {
+        let mut src = Buffer::alloc(10000).unwrap();
+        fastrand::fill(&mut src[0..5000]);
+        for (i, b) in src[5000..].iter_mut().enumerate() {
+            *b = b"repetitive data "[i % 16];
+        }
+        for max_block in [1, 100, 4096, 10000, 20000] {
+            let blocks = compress_blocked::<LZ4>(&src, max_block).unwrap();
+            assert_eq!(blocks.len(), src.len().div_ceil(max_block));
+            // Random blocks expand, only the decompressed size is bounded
+            let mut scratch = vec![0u8; max_block];
+            for (i, block) in blocks.iter().enumerate() {
+                let len = LZ4::decompress(block, &mut scratch).unwrap();
+                assert_eq!(len, src[i * max_block..].len().min(max_block));
+            }
+            let restored = decompress_blocked::<LZ4>(&blocks, max_block).unwrap();
+            assert_eq!(&restored[..], &src[..]);
+        }
+        let blocks = compress_blocked::<LZ4>(&src, 4096).unwrap();
+        assert!(decompress_blocked::<LZ4>(&blocks, 3000).is_err());
+    }
+
+    #[test]
+    fn test_should_compress() {
+        let mut src = Buffer::alloc(65536).unwrap();
+        fastrand::fill(&mut src);
+        assert!(!LZ4::should_compress(&src, 4096));
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = b"repetitive data "[i % 16];
+        }
+        assert!(LZ4::should_compress(&src, 4096));
+        // Only the sample is checked
+        src[0..4096].fill(0);
+        fastrand::fill(&mut src[4096..]);
+        assert!(LZ4::should_compress(&src, 4096));
+        assert!(!LZ4::should_compress(&src, 65536));
+    }
 }
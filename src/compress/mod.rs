@@ -1,4 +1,5 @@
-use std::io::Result;
+use crate::Buffer;
+use std::io::{Error, ErrorKind, Result};
 
 /// A trait for different compress method
 pub trait Compression {
@@ -21,8 +22,609 @@ pub trait Compression {
     ///
     ///  * dest: output buffer for decompressed data
     fn decompress(src: &[u8], dest: &mut [u8]) -> Result<usize>;
+
+    /// The version of the backend library, for diagnostics.
+    fn version() -> &'static str {
+        "unknown"
+    }
+
+    /// A cheap heuristic to skip incompressible data (already compressed or encrypted):
+    /// compress the leading `sample` bytes of `src`, return true if the ratio is below 0.9.
+    ///
+    /// Backends may override with something smarter.
+    fn should_compress(src: &[u8], sample: usize) -> bool {
+        let sample = &src[0..sample.min(src.len())];
+        if sample.is_empty() {
+            return false;
+        }
+        let mut dest = vec![0u8; Self::compress_bound(sample.len())];
+        match Self::compress(sample, &mut dest) {
+            Ok(compressed_len) => (compressed_len as f64) < sample.len() as f64 * 0.9,
+            Err(_) => false,
+        }
+    }
+}
+
+/// Store the data as-is, for [CompressionKind::None].
+pub struct Identity();
+
+impl Compression for Identity {
+    #[inline]
+    fn compress_bound(origin_len: usize) -> usize {
+        origin_len
+    }
+
+    #[inline]
+    fn compress(src: &[u8], dest: &mut [u8]) -> Result<usize> {
+        if dest.len() < src.len() {
+            return Err(Error::other("dest too small"));
+        }
+        dest[0..src.len()].copy_from_slice(src);
+        Ok(src.len())
+    }
+
+    #[inline]
+    fn decompress(src: &[u8], dest: &mut [u8]) -> Result<usize> {
+        Self::compress(src, dest)
+    }
+}
+
+/// The compression algorithm as runtime data, which can be stored in a file header,
+/// dispatching to the [Compression] implementation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(u8)]
+#[non_exhaustive]
+pub enum CompressionKind {
+    /// Not compressed, see [Identity]
+    None = 0,
+    /// Enabled with feature `lz4`
+    #[cfg(feature = "lz4")]
+    Lz4 = 1,
+}
+
+impl CompressionKind {
+    /// The id to be stored, see [CompressionKind::from_id()]
+    #[inline]
+    pub fn id(&self) -> u8 {
+        *self as u8
+    }
+
+    /// Return None if the id is unknown, or the algorithm is not enabled.
+    #[inline]
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Self::None),
+            #[cfg(feature = "lz4")]
+            1 => Some(Self::Lz4),
+            _ => None,
+        }
+    }
+
+    /// The name in configuration, see [CompressionKind::from_name()]
+    #[inline]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => "lz4",
+        }
+    }
+
+    /// Parse the name in configuration, case-insensitive, "identity" is an alias of "none".
+    ///
+    /// Return `Unsupported` if the algorithm is known but not enabled, `InvalidInput` if unknown.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "none" | "identity" => Ok(Self::None),
+            #[cfg(feature = "lz4")]
+            "lz4" => Ok(Self::Lz4),
+            #[cfg(not(feature = "lz4"))]
+            "lz4" => Err(Error::new(ErrorKind::Unsupported, "lz4 is not enabled")),
+            _ => {
+                Err(Error::new(ErrorKind::InvalidInput, format!("unknown compression {:?}", name)))
+            }
+        }
+    }
+
+    /// See [Compression::compress_bound()]
+    #[inline]
+    pub fn compress_bound(&self, origin_len: usize) -> usize {
+        match self {
+            Self::None => Identity::compress_bound(origin_len),
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => lz4::LZ4::compress_bound(origin_len),
+        }
+    }
+
+    /// See [Compression::compress()]
+    #[inline]
+    pub fn compress(&self, src: &[u8], dest: &mut [u8]) -> Result<usize> {
+        match self {
+            Self::None => Identity::compress(src, dest),
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => lz4::LZ4::compress(src, dest),
+        }
+    }
+
+    /// See [Compression::decompress()]
+    #[inline]
+    pub fn decompress(&self, src: &[u8], dest: &mut [u8]) -> Result<usize> {
+        match self {
+            Self::None => Identity::decompress(src, dest),
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => lz4::LZ4::decompress(src, dest),
+        }
+    }
+}
+
+/// Try each of `kinds`, return the one produced the smallest output with the compressed buffer.
+///
+/// If none is smaller than `src`, return [CompressionKind::None] with a copy of `src`.
+pub fn compress_best(src: &[u8], kinds: &[CompressionKind]) -> Result<(CompressionKind, Buffer)> {
+    if src.len() >= i32::MAX as usize {
+        return Err(Error::new(ErrorKind::InvalidInput, "src too large"));
+    }
+    let mut best: Option<(CompressionKind, Buffer)> = None;
+    for kind in kinds {
+        if *kind == CompressionKind::None {
+            continue;
+        }
+        let bound = kind.compress_bound(src.len());
+        if bound >= i32::MAX as usize {
+            continue;
+        }
+        let mut buf = Buffer::alloc(bound.max(1) as i32)?;
+        let len = kind.compress(src, &mut buf)?;
+        if len >= src.len() || best.as_ref().is_some_and(|(_, b)| b.len() <= len) {
+            continue;
+        }
+        buf.set_len(len);
+        best = Some((*kind, buf));
+    }
+    match best {
+        Some(best) => Ok(best),
+        None => Ok((CompressionKind::None, Buffer::from(src.to_vec()))),
+    }
+}
+
+/// Decompress `src` with `S` into a scratch buffer, and recompress with `D`, to migrate data
+/// between codecs. Return the new compressed buffer.
+///
+/// `orig_len`: the decompressed size, return `InvalidData` if the intermediate length differs,
+/// `InvalidInput` if it is not less than [MAX_BUFFER_SIZE](crate::MAX_BUFFER_SIZE).
+pub fn transcode<S: Compression, D: Compression>(src: &[u8], orig_len: usize) -> Result<Buffer> {
+    // Check before casting, compress_bound() may truncate
+    if orig_len >= crate::MAX_BUFFER_SIZE {
+        return Err(Error::new(ErrorKind::InvalidInput, "orig_len too large"));
+    }
+    let bound = D::compress_bound(orig_len);
+    if bound >= i32::MAX as usize {
+        return Err(Error::new(ErrorKind::InvalidInput, "orig_len too large"));
+    }
+    let mut scratch = Buffer::alloc(orig_len.max(1) as i32)?;
+    let len = S::decompress(src, &mut scratch[0..orig_len])?;
+    if len != orig_len {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("decompressed len {} != orig_len {}", len, orig_len),
+        ));
+    }
+    let mut out = Buffer::alloc(bound.max(1) as i32)?;
+    let compressed_len = D::compress(&scratch[0..orig_len], &mut out)?;
+    out.set_len(compressed_len);
+    Ok(out)
+}
+
+const DICT_MAGIC: &[u8; 4] = b"IOBD";
+const DICT_VERSION: u8 = 1;
+const DICT_HEADER_LEN: usize = 12;
+
+/// Serialize a trained dictionary for `kind`, to ship to the decompressor.
+///
+/// The format is: magic `"IOBD"`, kind id (u8), format version (u8), 2 reserved bytes,
+/// the dictionary length (u32 little endian), then the dictionary bytes.
+/// See [load_dictionary()].
+///
+/// # Panic
+///
+/// If the dictionary is too large for a Buffer
+pub fn save_dictionary(dict: &[u8], kind: CompressionKind) -> Buffer {
+    let total = DICT_HEADER_LEN + dict.len();
+    assert!(total < i32::MAX as usize, "dictionary of {} bytes is too large", dict.len());
+    let mut buf = Buffer::alloc(total as i32).unwrap();
+    buf[0..4].copy_from_slice(DICT_MAGIC);
+    buf[4] = kind.id();
+    buf[5] = DICT_VERSION;
+    buf[6..8].copy_from_slice(&[0, 0]);
+    buf[8..12].copy_from_slice(&(dict.len() as u32).to_le_bytes());
+    buf[DICT_HEADER_LEN..].copy_from_slice(dict);
+    buf
+}
+
+/// Parse the output of [save_dictionary()], return the [CompressionKind] tagged and the dictionary.
+///
+/// Return `InvalidData` if the magic, version or length mismatch,
+/// `Unsupported` if the kind is unknown or not enabled.
+/// The caller should reject a kind different from its decompressor.
+pub fn load_dictionary(src: &[u8]) -> Result<(CompressionKind, Buffer)> {
+    if src.len() < DICT_HEADER_LEN || &src[0..4] != DICT_MAGIC {
+        return Err(Error::new(ErrorKind::InvalidData, "not a dictionary"));
+    }
+    if src[5] != DICT_VERSION {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("unknown dictionary version {}", src[5]),
+        ));
+    }
+    let Some(kind) = CompressionKind::from_id(src[4]) else {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("unsupported compression id {}", src[4]),
+        ));
+    };
+    let len = u32::from_le_bytes(src[8..12].try_into().unwrap()) as usize;
+    if src.len() - DICT_HEADER_LEN != len {
+        return Err(Error::new(ErrorKind::InvalidData, "dictionary length mismatch"));
+    }
+    Ok((kind, Buffer::from(src[DICT_HEADER_LEN..].to_vec())))
+}
+
+/// The same as [load_dictionary()], but return `InvalidInput` if the dictionary is tagged for
+/// another kind than `expected`.
+pub fn load_dictionary_for(src: &[u8], expected: CompressionKind) -> Result<Buffer> {
+    let (kind, dict) = load_dictionary(src)?;
+    if kind != expected {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("dictionary is for {:?}, not {:?}", kind, expected),
+        ));
+    }
+    Ok(dict)
+}
+
+/// List the enabled compression backends, with (name, version).
+pub fn backend_versions() -> Vec<(&'static str, &'static str)> {
+    vec![
+        #[cfg(feature = "lz4")]
+        ("lz4", lz4::LZ4::version()),
+    ]
+}
+
+/// Return `original_len / compressed_len`, for logging and metrics.
+///
+/// Return infinity when `compressed_len` is zero, and NaN when both are zero.
+#[inline]
+pub fn compression_ratio(original_len: usize, compressed_len: usize) -> f64 {
+    original_len as f64 / compressed_len as f64
+}
+
+/// Compress `src` into `dst` (up to its capacity), on success set `dst.len()` to the compressed size
+/// and return it. On error, the len of `dst` is not changed.
+pub fn compress_into<C: Compression>(src: &[u8], dst: &mut Buffer) -> Result<usize> {
+    let len = dst.len();
+    dst.set_len(dst.capacity());
+    match C::compress(src, dst) {
+        Ok(compressed_len) => {
+            dst.set_len(compressed_len);
+            Ok(compressed_len)
+        }
+        Err(e) => {
+            dst.set_len(len);
+            Err(e)
+        }
+    }
+}
+
+/// Compress many small parts back-to-back into one output buffer.
+///
+/// On success, return the output buffer, and the index of (offset, compressed_len) for each part.
+pub fn compress_batch<C: Compression>(parts: &[&[u8]]) -> Result<(Buffer, Vec<(u32, u32)>)> {
+    let mut bound: usize = 0;
+    for part in parts {
+        bound += C::compress_bound(part.len());
+    }
+    if bound >= i32::MAX as usize {
+        return Err(Error::new(ErrorKind::InvalidInput, "batch too large"));
+    }
+    let mut buf = Buffer::alloc(bound.max(1) as i32)?;
+    let mut index = Vec::with_capacity(parts.len());
+    let mut offset: usize = 0;
+    for part in parts {
+        let compressed_len = C::compress(part, &mut buf[offset..])?;
+        index.push((offset as u32, compressed_len as u32));
+        offset += compressed_len;
+    }
+    buf.set_len(offset);
+    Ok((buf, index))
+}
+
+/// Decompress the parts produced by [compress_batch()], with the index returned.
+///
+/// Each part is decompressed into one scratch buffer of `max_part_len`, and copied out to a buffer
+/// of its exact size.
+///
+/// `max_part_len`: the upper bound of a decompressed part, must be larger than zero.
+pub fn decompress_batch<C: Compression>(
+    src: &[u8], index: &[(u32, u32)], max_part_len: usize,
+) -> Result<Vec<Buffer>> {
+    assert!(max_part_len > 0 && max_part_len < i32::MAX as usize);
+    let mut scratch = Buffer::alloc(max_part_len as i32)?;
+    let mut parts = Vec::with_capacity(index.len());
+    for &(offset, compressed_len) in index {
+        let (start, end) = (offset as usize, offset as usize + compressed_len as usize);
+        if end > src.len() {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "batch index out of range"));
+        }
+        let len = C::decompress(&src[start..end], &mut scratch)?;
+        parts.push(Buffer::from(scratch[0..len].to_vec()));
+    }
+    Ok(parts)
+}
+
+/// Split `src` into blocks of `max_block` bytes (the last one may be shorter), and compress
+/// each block independently, for formats limiting the size of a block.
+///
+/// Return the compressed blocks, restore with [decompress_blocked()] and the same `max_block`.
+pub fn compress_blocked<C: Compression>(src: &[u8], max_block: usize) -> Result<Vec<Buffer>> {
+    if max_block == 0 || C::compress_bound(max_block) >= i32::MAX as usize {
+        return Err(Error::new(ErrorKind::InvalidInput, "invalid max_block"));
+    }
+    let mut blocks = Vec::with_capacity(src.len().div_ceil(max_block));
+    for chunk in src.chunks(max_block) {
+        let mut block = Buffer::alloc(C::compress_bound(chunk.len()).max(1) as i32)?;
+        let compressed_len = C::compress(chunk, &mut block)?;
+        block.set_len(compressed_len);
+        blocks.push(block);
+    }
+    Ok(blocks)
+}
+
+/// Decompress the blocks produced by [compress_blocked()] and concatenate them.
+///
+/// A block decompressed larger than `max_block`, or a short block not in the last place,
+/// is an `InvalidData` error.
+pub fn decompress_blocked<C: Compression>(blocks: &[Buffer], max_block: usize) -> Result<Buffer> {
+    let total = blocks.len().checked_mul(max_block).filter(|n| *n < i32::MAX as usize);
+    let Some(total) = total.filter(|_| max_block > 0) else {
+        return Err(Error::new(ErrorKind::InvalidInput, "invalid max_block"));
+    };
+    let mut buf = Buffer::alloc(total.max(1) as i32)?;
+    let mut offset = 0;
+    for (i, block) in blocks.iter().enumerate() {
+        let len = C::decompress(block, &mut buf[offset..offset + max_block])?;
+        if len < max_block && i + 1 < blocks.len() {
+            return Err(Error::new(ErrorKind::InvalidData, format!("short block {}", i)));
+        }
+        offset += len;
+    }
+    buf.set_len(offset);
+    Ok(buf)
+}
+
+/// Decompress untrusted data into a new buffer, reject the frame with `InvalidInput`
+/// before allocating when `claimed_len > max_len`, preventing decompression bombs.
+///
+/// `claimed_len`: the decompressed size declared by the frame, used as the output capacity.
+pub fn decompress_capped<C: Compression>(
+    src: &[u8], claimed_len: usize, max_len: usize,
+) -> Result<Buffer> {
+    if claimed_len > max_len || claimed_len >= i32::MAX as usize {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("claimed len {} exceeds the cap {}", claimed_len, max_len),
+        ));
+    }
+    let mut buf = Buffer::alloc(claimed_len.max(1) as i32)?;
+    let len = C::decompress(src, &mut buf[0..claimed_len])?;
+    buf.set_len(len);
+    Ok(buf)
+}
+
+/// Round-trip random buffers of varying length (including 0, 1 and `max_len`) through the codec,
+/// assert it is lossless and within `compress_bound()`, and that a too small destination errors.
+///
+/// The acceptance test for a [Compression] implementation. Enabled with feature `rand`.
+///
+/// # Panic
+///
+/// On any failure
+#[cfg(feature = "rand")]
+pub fn validate_codec<C: Compression>(iterations: usize, max_len: usize) {
+    let mut lens = vec![0, 1, max_len];
+    for _ in 0..iterations {
+        lens.push(fastrand::usize(0..=max_len));
+    }
+    for len in lens {
+        let mut src = vec![0u8; len];
+        match fastrand::u8(0..3) {
+            0 => fastrand::fill(&mut src),
+            1 => crate::rand_buffer(&mut src),
+            _ => {}
+        }
+        let bound = C::compress_bound(len);
+        let mut compressed = vec![0u8; bound];
+        let compressed_len = C::compress(&src, &mut compressed)
+            .unwrap_or_else(|e| panic!("compress len {} failed: {}", len, e));
+        assert!(compressed_len <= bound, "compressed {} > bound {}", compressed_len, bound);
+        let mut decompressed = vec![0u8; len];
+        let decompressed_len = C::decompress(&compressed[0..compressed_len], &mut decompressed)
+            .unwrap_or_else(|e| panic!("decompress len {} failed: {}", len, e));
+        assert_eq!(decompressed_len, len);
+        assert!(decompressed == src, "round-trip len {} mismatch", len);
+        // too small destination
+        if compressed_len > 0 {
+            assert!(C::compress(&src, &mut compressed[0..compressed_len - 1]).is_err());
+        }
+        if len > 0 {
+            assert!(C::decompress(&compressed[0..compressed_len], &mut decompressed[1..]).is_err());
+        }
+    }
 }
 
 #[cfg(any(feature = "lz4", doc))]
 /// Enabled with feature `lz4`
 pub mod lz4;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "rand")]
+    #[test]
+    fn test_identity() {
+        validate_codec::<Identity>(100, 4096);
+    }
+
+    #[test]
+    fn test_compression_kind() {
+        let kinds = [
+            CompressionKind::None,
+            #[cfg(feature = "lz4")]
+            CompressionKind::Lz4,
+        ];
+        let src = [1u8; 1000];
+        for kind in kinds {
+            assert_eq!(CompressionKind::from_id(kind.id()), Some(kind));
+            let mut compressed = vec![0u8; kind.compress_bound(src.len())];
+            let compressed_len = kind.compress(&src, &mut compressed).unwrap();
+            let mut decompressed = vec![0u8; src.len()];
+            let len = kind.decompress(&compressed[0..compressed_len], &mut decompressed).unwrap();
+            assert_eq!(len, src.len());
+            assert_eq!(&decompressed[..], &src[..]);
+        }
+        assert_eq!(CompressionKind::from_id(255), None);
+    }
+
+    #[test]
+    fn test_compress_best() {
+        let src = [1u8; 1000];
+        let (kind, buf) = compress_best(&src, &[CompressionKind::None]).unwrap();
+        assert_eq!(kind, CompressionKind::None);
+        assert_eq!(&buf[..], &src[..]);
+        let (kind, buf) = compress_best(&[], &[]).unwrap();
+        assert_eq!((kind, buf.len()), (CompressionKind::None, 0));
+    }
+
+    #[test]
+    fn test_dictionary() {
+        let dict = b"a trained dictionary";
+        let saved = save_dictionary(dict, CompressionKind::None);
+        assert_eq!(saved.len(), DICT_HEADER_LEN + dict.len());
+        let (kind, loaded) = load_dictionary(&saved).unwrap();
+        assert_eq!(kind, CompressionKind::None);
+        assert_eq!(&loaded[..], &dict[..]);
+        // Empty dictionary
+        let (_, loaded) = load_dictionary(&save_dictionary(b"", CompressionKind::None)).unwrap();
+        assert_eq!(loaded.len(), 0);
+        // Truncated
+        let e = load_dictionary(&saved[0..saved.len() - 1]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        assert_eq!(load_dictionary(&saved[0..8]).unwrap_err().kind(), ErrorKind::InvalidData);
+        // Unknown algorithm
+        let mut bad = saved.clone();
+        bad[4] = 255;
+        assert_eq!(load_dictionary(&bad).unwrap_err().kind(), ErrorKind::Unsupported);
+        let mut bad = saved.clone();
+        bad[5] = 2;
+        assert_eq!(load_dictionary(&bad).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn test_dictionary_mismatch() {
+        let saved = save_dictionary(b"lz4 dict", CompressionKind::Lz4);
+        let (kind, _) = load_dictionary(&saved).unwrap();
+        assert_eq!(kind, CompressionKind::Lz4);
+        let dict = load_dictionary_for(&saved, CompressionKind::Lz4).unwrap();
+        assert_eq!(&dict[..], b"lz4 dict");
+        let e = load_dictionary_for(&saved, CompressionKind::None).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_should_compress_identity() {
+        assert!(!Identity::should_compress(&[0u8; 4096], 1024));
+        assert!(!Identity::should_compress(&[], 1024));
+    }
+
+    #[test]
+    fn test_compression_name() {
+        assert_eq!(CompressionKind::from_name("none").unwrap(), CompressionKind::None);
+        assert_eq!(CompressionKind::from_name("Identity").unwrap(), CompressionKind::None);
+        assert_eq!(CompressionKind::None.name(), "none");
+        #[cfg(feature = "lz4")]
+        {
+            assert_eq!(CompressionKind::from_name("LZ4").unwrap(), CompressionKind::Lz4);
+            assert_eq!(CompressionKind::Lz4.name(), "lz4");
+        }
+        #[cfg(not(feature = "lz4"))]
+        {
+            let e = CompressionKind::from_name("lz4").unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::Unsupported);
+        }
+        let e = CompressionKind::from_name("brotli").unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_compression_ratio() {
+        assert_eq!(compression_ratio(4096, 1024), 4.0);
+        assert_eq!(compression_ratio(100, 200), 0.5);
+        assert_eq!(compression_ratio(0, 10), 0.0);
+        assert!(compression_ratio(10, 0).is_infinite());
+        assert!(compression_ratio(0, 0).is_nan());
+    }
+
+    #[test]
+    fn test_compress_into() {
+        let src = [1u8; 1000];
+        let mut dst = Buffer::alloc(2000).unwrap();
+        dst.set_len(10);
+        assert_eq!(compress_into::<Identity>(&src, &mut dst).unwrap(), 1000);
+        assert_eq!(dst.len(), 1000);
+        assert_eq!(&dst[..], &src[..]);
+        // Too small
+        let mut dst = Buffer::alloc(500).unwrap();
+        dst.set_len(10);
+        assert!(compress_into::<Identity>(&src, &mut dst).is_err());
+        assert_eq!(dst.len(), 10);
+    }
+
+    #[test]
+    fn test_transcode() {
+        let stored = transcode::<Identity, Identity>(b"hello", 5).unwrap();
+        assert_eq!(&stored[..], b"hello");
+        assert!(transcode::<Identity, Identity>(b"hello", 4).is_err());
+        let e = transcode::<Identity, Identity>(b"hello", 6).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        // Too large orig_len must not be truncated by casting
+        for orig_len in [crate::MAX_BUFFER_SIZE, (1 << 32) + 10, usize::MAX] {
+            let e = transcode::<Identity, Identity>(b"hello", orig_len).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn test_compress_blocked() {
+        let src: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+        for max_block in [1, 100, 4096, 10000, 20000] {
+            let blocks = compress_blocked::<Identity>(&src, max_block).unwrap();
+            assert_eq!(blocks.len(), src.len().div_ceil(max_block));
+            let mut scratch = vec![0u8; max_block];
+            for (i, block) in blocks.iter().enumerate() {
+                let len = Identity::decompress(block, &mut scratch).unwrap();
+                assert_eq!(len, src[i * max_block..].len().min(max_block));
+            }
+            let restored = decompress_blocked::<Identity>(&blocks, max_block).unwrap();
+            assert_eq!(&restored[..], &src[..]);
+        }
+        assert!(compress_blocked::<Identity>(&[], 100).unwrap().is_empty());
+        assert_eq!(decompress_blocked::<Identity>(&[], 100).unwrap().len(), 0);
+        assert!(compress_blocked::<Identity>(&src, 0).is_err());
+        // Boundaries not matching
+        let blocks = compress_blocked::<Identity>(&src, 4096).unwrap();
+        assert!(decompress_blocked::<Identity>(&blocks, 3000).is_err());
+        let e = decompress_blocked::<Identity>(&blocks[1..], 5000).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+}
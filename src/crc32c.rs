@@ -0,0 +1,257 @@
+use crate::Buffer;
+use nix::errno::Errno;
+use std::{fmt, marker::PhantomData};
+
+/// CRC32C (Castagnoli) reflected polynomial
+const POLY: u32 = 0x82F63B78;
+
+const TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ POLY } else { crc >> 1 };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Incremental CRC32C (Castagnoli, as used by iSCSI / ext4 / btrfs),
+/// to compute one checksum over a sequence of buffers.
+///
+/// ```
+/// use io_buffer::{Buffer, Crc32cHasher, crc32c};
+/// let mut hasher = Crc32cHasher::new();
+/// hasher.update(b"1234");
+/// hasher.update_buffer(&Buffer::from(b"56789".to_vec()));
+/// assert_eq!(hasher.finalize(), 0xE3069283);
+/// assert_eq!(crc32c(b"123456789"), 0xE3069283);
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Crc32cHasher {
+    state: u32,
+}
+
+impl Default for Crc32cHasher {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Crc32cHasher {
+    #[inline]
+    pub fn new() -> Self {
+        Self { state: !0 }
+    }
+
+    /// Feed more data
+    #[inline]
+    pub fn update(&mut self, data: &[u8]) {
+        let mut crc = self.state;
+        for b in data {
+            crc = TABLE[((crc ^ *b as u32) & 0xff) as usize] ^ (crc >> 8);
+        }
+        self.state = crc;
+    }
+
+    /// Feed the content of a buffer within `len()`
+    #[inline]
+    pub fn update_buffer(&mut self, buf: &Buffer) {
+        self.update(buf.as_ref())
+    }
+
+    /// Return the checksum of all the data fed
+    #[inline]
+    pub fn finalize(&self) -> u32 {
+        !self.state
+    }
+}
+
+/// Incremental checksum algorithm, for the checksum methods of [Buffer] generic over it.
+///
+/// The digest is widened to u64, and stored as `SIZE` little endian bytes in a trailer.
+///
+/// ```
+/// use io_buffer::{Checksum, Crc32cHasher};
+/// assert_eq!(Crc32cHasher::checksum(b"123456789"), 0xE3069283);
+/// ```
+pub trait Checksum: Sized {
+    /// Bytes of the digest, at most 8, [Buffer::pread_verified()] returns `EINVAL` otherwise
+    const SIZE: usize;
+
+    fn new() -> Self;
+
+    /// Feed more data
+    fn update(&mut self, data: &[u8]);
+
+    /// Return the digest of all the data fed
+    fn finalize(&self) -> u64;
+
+    /// Return the digest of data in one pass
+    #[inline]
+    fn checksum(data: &[u8]) -> u64 {
+        let mut k = Self::new();
+        k.update(data);
+        k.finalize()
+    }
+}
+
+impl Checksum for Crc32cHasher {
+    const SIZE: usize = 4;
+
+    #[inline]
+    fn new() -> Self {
+        Crc32cHasher::new()
+    }
+
+    #[inline]
+    fn update(&mut self, data: &[u8]) {
+        Crc32cHasher::update(self, data)
+    }
+
+    #[inline]
+    fn finalize(&self) -> u64 {
+        Crc32cHasher::finalize(self) as u64
+    }
+}
+
+/// Error of [Buffer::pread_verified()]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VerifyError {
+    /// Invalid argument, or IO error
+    Errno(Errno),
+    /// The checksum computed over the data mismatches the one stored in the trailer
+    ChecksumMismatch { computed: u64, stored: u64 },
+}
+
+impl From<Errno> for VerifyError {
+    #[inline]
+    fn from(e: Errno) -> Self {
+        Self::Errno(e)
+    }
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Errno(e) => e.fmt(f),
+            Self::ChecksumMismatch { computed, stored } => {
+                write!(f, "checksum mismatch, computed {:#x} stored {:#x}", computed, stored)
+            }
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Return the CRC32C of data in one pass, see [Crc32cHasher].
+#[inline]
+pub fn crc32c(data: &[u8]) -> u32 {
+    let mut hasher = Crc32cHasher::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// Guard asserting a buffer is not modified within a scope, returned by [Buffer::checksum_guard()].
+///
+/// On drop, the checksum `K` of the content is computed again and compared, a mismatch panics on
+/// debug mode, or is logged as error on release mode. Catches mutation through an aliasing c ref,
+/// which the mutability check skipped on release mode lets through.
+pub struct ChecksumGuard<'a, K: Checksum> {
+    buf: &'a Buffer,
+    checksum: u64,
+    _phan: PhantomData<K>,
+}
+
+impl<'a, K: Checksum> ChecksumGuard<'a, K> {
+    #[inline]
+    pub(crate) fn new(buf: &'a Buffer) -> Self {
+        Self { buf, checksum: K::checksum(buf.as_ref()), _phan: PhantomData }
+    }
+
+    /// Return the checksum captured
+    #[inline(always)]
+    pub fn checksum(&self) -> u64 {
+        self.checksum
+    }
+}
+
+impl<K: Checksum> Drop for ChecksumGuard<'_, K> {
+    fn drop(&mut self) {
+        let checksum = K::checksum(self.buf.as_ref());
+        if checksum != self.checksum && !std::thread::panicking() {
+            let msg = format!(
+                "buffer {:p} modified within the guard, checksum {:#x} -> {:#x}",
+                self.buf.get_raw(),
+                self.checksum,
+                checksum
+            );
+            if cfg!(debug_assertions) {
+                panic!("{}", msg);
+            }
+            log::error!("{}", msg);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32c() {
+        assert_eq!(crc32c(b""), 0);
+        assert_eq!(crc32c(b"123456789"), 0xE3069283);
+        assert_eq!(crc32c(&[0u8; 32]), 0x8A9136AA);
+    }
+
+    #[test]
+    fn test_crc32c_split() {
+        let data: Vec<u8> = (0..10000u32).map(|i| (i * 7 + i / 13) as u8).collect();
+        let expected = crc32c(&data);
+        for (a, b) in [(0, 0), (1, 2), (100, 5000), (9999, 10000), (3333, 3333)] {
+            let mut hasher = Crc32cHasher::new();
+            hasher.update_buffer(&Buffer::from(data[0..a].to_vec()));
+            hasher.update(&data[a..b]);
+            hasher.update(&data[b..]);
+            assert_eq!(hasher.finalize(), expected, "split at {} {}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_checksum_trait() {
+        assert_eq!(<Crc32cHasher as Checksum>::checksum(b""), 0);
+        let mut k = <Crc32cHasher as Checksum>::new();
+        Checksum::update(&mut k, b"1234");
+        Checksum::update(&mut k, b"56789");
+        assert_eq!(Checksum::finalize(&k), 0xE3069283u64);
+    }
+
+    #[test]
+    fn test_checksum_guard() {
+        let buf = Buffer::from(b"123456789".to_vec());
+        {
+            let guard = buf.checksum_guard::<Crc32cHasher>();
+            assert_eq!(guard.checksum(), 0xE3069283);
+        }
+        assert_eq!(&buf[..], b"123456789");
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "modified within the guard")]
+    fn test_checksum_guard_modified() {
+        let mut data = *b"123456789";
+        let buf = unsafe { Buffer::from_slice_ref(&data) };
+        let _guard = buf.checksum_guard::<Crc32cHasher>();
+        // Mutation through an alias
+        let mut alias = Buffer::from_c_ref_mut(data.as_mut_ptr() as *mut libc::c_void, 9);
+        alias[0] = b'x';
+    }
+}
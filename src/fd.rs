@@ -0,0 +1,150 @@
+#![allow(clippy::needless_return)]
+
+use crate::Buffer;
+use nix::errno::Errno;
+use std::os::fd::RawFd;
+
+/// Query the logical block size of a block device by ioctl BLKSSZGET,
+/// which is the alignment required by O_DIRECT.
+///
+/// Return `ENOTTY` if fd is not a block device.
+#[cfg(target_os = "linux")]
+#[inline]
+pub fn device_block_size(fd: RawFd) -> Result<u32, Errno> {
+    let mut size: libc::c_int = 0;
+    let res = unsafe { libc::ioctl(fd, libc::BLKSSZGET, &mut size as *mut libc::c_int) };
+    if res < 0 {
+        return Err(Errno::last());
+    }
+    return Ok(size as u32);
+}
+
+/// Query the physical block size of a block device by ioctl BLKPBSZGET.
+///
+/// Return `ENOTTY` if fd is not a block device.
+#[cfg(target_os = "linux")]
+#[inline]
+pub fn device_physical_block_size(fd: RawFd) -> Result<u32, Errno> {
+    let mut size: libc::c_uint = 0;
+    let res = unsafe { libc::ioctl(fd, libc::BLKPBSZGET, &mut size as *mut libc::c_uint) };
+    if res < 0 {
+        return Err(Errno::last());
+    }
+    return Ok(size);
+}
+
+/// Copy `len` bytes from `src_fd` to `dst_fd` (from their current offsets), through `buf`.
+///
+/// Reuse `buf` (up to its capacity) for every chunk, with an aligned buffer it is O_DIRECT-capable.
+/// Handles short reads / writes and `EINTR`. Return the total bytes copied,
+/// which is less than `len` when reaching EOF of `src_fd`.
+///
+/// **NOTE**: The content and len of `buf` is overwritten.
+///
+/// Return `EINVAL` if `buf` is not mutable or has zero capacity, `EIO` if write() makes no progress.
+pub fn copy_fd_to_fd(
+    src_fd: RawFd, dst_fd: RawFd, len: usize, buf: &mut Buffer,
+) -> Result<usize, Errno> {
+    if !buf.is_mutable() || buf.capacity() == 0 {
+        return Err(Errno::EINVAL);
+    }
+    buf.set_len(buf.capacity());
+    let mut total: usize = 0;
+    while total < len {
+        let chunk = buf.len().min(len - total);
+        let read = loop {
+            let res = sys_read(src_fd, buf.get_raw_mut(), chunk);
+            if res >= 0 {
+                break res as usize;
+            }
+            let errno = Errno::last();
+            if errno != Errno::EINTR {
+                return Err(errno);
+            }
+        };
+        if read == 0 {
+            break;
+        }
+        let mut written: usize = 0;
+        while written < read {
+            let data = &buf[written..read];
+            let res = sys_write(dst_fd, data.as_ptr(), data.len());
+            if res < 0 {
+                let errno = Errno::last();
+                if errno != Errno::EINTR {
+                    return Err(errno);
+                }
+                continue;
+            }
+            if res == 0 {
+                return Err(Errno::EIO);
+            }
+            written += res as usize;
+        }
+        total += read;
+    }
+    return Ok(total);
+}
+
+/// read(), with failpoints "copy_fd_read_err" and "copy_fd_read_short" when feature `fail` enabled.
+#[inline]
+fn sys_read(fd: RawFd, ptr: *mut u8, len: usize) -> isize {
+    // Inject the errno of `return(errno)`, EIO by default
+    #[cfg(feature = "fail")]
+    fail::fail_point!("copy_fd_read_err", |arg: Option<String>| {
+        Errno::set_raw(arg.and_then(|s| s.parse().ok()).unwrap_or(libc::EIO));
+        -1
+    });
+    // Halve the size to simulate a short read
+    #[cfg(feature = "fail")]
+    fail::fail_point!("copy_fd_read_short", |_| unsafe {
+        libc::read(fd, ptr as *mut libc::c_void, (len / 2).max(1))
+    });
+    unsafe { libc::read(fd, ptr as *mut libc::c_void, len) }
+}
+
+/// write(), with failpoints "copy_fd_write_err" and "copy_fd_write_short" when feature `fail` enabled.
+#[inline]
+fn sys_write(fd: RawFd, ptr: *const u8, len: usize) -> isize {
+    // Inject the errno of `return(errno)`, EIO by default
+    #[cfg(feature = "fail")]
+    fail::fail_point!("copy_fd_write_err", |arg: Option<String>| {
+        Errno::set_raw(arg.and_then(|s| s.parse().ok()).unwrap_or(libc::EIO));
+        -1
+    });
+    // Halve the size to simulate a short write
+    #[cfg(feature = "fail")]
+    fail::fail_point!("copy_fd_write_short", |_| unsafe {
+        libc::write(fd, ptr as *const libc::c_void, (len / 2).max(1))
+    });
+    unsafe { libc::write(fd, ptr as *const libc::c_void, len) }
+}
+
+/// pread() into `buf` at `offset` until it is full or reaching EOF, retry on `EINTR`.
+/// Return the bytes read.
+pub(crate) fn pread_full(fd: RawFd, buf: &mut [u8], offset: u64) -> Result<usize, Errno> {
+    let mut done: usize = 0;
+    while done < buf.len() {
+        let data = &mut buf[done..];
+        let res = unsafe {
+            libc::pread(
+                fd,
+                data.as_mut_ptr() as *mut libc::c_void,
+                data.len(),
+                (offset + done as u64) as libc::off_t,
+            )
+        };
+        if res < 0 {
+            let errno = Errno::last();
+            if errno != Errno::EINTR {
+                return Err(errno);
+            }
+            continue;
+        }
+        if res == 0 {
+            break;
+        }
+        done += res as usize;
+    }
+    return Ok(done);
+}
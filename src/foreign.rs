@@ -0,0 +1,167 @@
+use crate::Buffer;
+use libc::c_void;
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::Arc,
+};
+
+/// A [Buffer] view over foreign memory, released by a custom dropper on drop.
+///
+/// Returned by [Buffer::from_raw_with_dropper()], for memory from arena allocators,
+/// GPU-pinned memory, or C libraries with their own free function.
+/// Also returned by [Buffer::from_arc_slice()] as an immutable view keeping the Arc alive.
+///
+/// Clone the inner buffer with [as_buffer()](ForeignBuffer::as_buffer()) to get a fresh owned copy.
+/// Mutable access is only through `&mut [u8]`, so the inner buffer can never be replaced,
+/// and panics if the view is immutable.
+pub struct ForeignBuffer {
+    buf: Buffer,
+    /// The pointer passed in, or the boxed Arc of [Buffer::from_arc_slice()],
+    /// which is handed back to the dropper
+    ptr: *mut c_void,
+    dropper: extern "C" fn(*mut c_void),
+}
+
+unsafe impl Send for ForeignBuffer {}
+
+unsafe impl Sync for ForeignBuffer {}
+
+impl fmt::Debug for ForeignBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ForeignBuffer({:?})", self.buf)
+    }
+}
+
+impl ForeignBuffer {
+    /// # Safety
+    ///
+    /// `ptr` must be valid for read and write of `size` bytes until the dropper is called.
+    #[inline]
+    pub(crate) unsafe fn new(
+        ptr: *mut c_void, size: i32, dropper: extern "C" fn(*mut c_void),
+    ) -> Self {
+        Self { buf: Buffer::from_c_ref_mut(ptr, size), ptr, dropper }
+    }
+
+    /// Wrap the content of `arc` as an immutable buffer, and keep the Arc alive until drop.
+    ///
+    /// # Panic
+    ///
+    /// If the length >= MAX_BUFFER_SIZE
+    #[inline]
+    pub(crate) fn from_arc(arc: Arc<[u8]>) -> Self {
+        // SAFETY: the content of the Arc does not move, and lives until the dropper is called
+        let buf = unsafe { Buffer::from_slice_ref(&arc) };
+        let ptr = Box::into_raw(Box::new(arc)) as *mut c_void;
+        Self { buf, ptr, dropper: drop_arc }
+    }
+
+    /// Borrow as a [Buffer], for APIs that need one.
+    #[inline(always)]
+    pub fn as_buffer(&self) -> &Buffer {
+        &self.buf
+    }
+}
+
+impl Deref for ForeignBuffer {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        self.buf.as_ref()
+    }
+}
+
+/// Will panic if the view is immutable, on release mode as well.
+impl DerefMut for ForeignBuffer {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [u8] {
+        assert!(self.buf.is_mutable(), "ForeignBuffer is immutable");
+        self.buf.as_mut()
+    }
+}
+
+impl AsRef<[u8]> for ForeignBuffer {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self.buf.as_ref()
+    }
+}
+
+impl AsRef<Buffer> for ForeignBuffer {
+    #[inline]
+    fn as_ref(&self) -> &Buffer {
+        &self.buf
+    }
+}
+
+extern "C" fn drop_arc(ptr: *mut c_void) {
+    drop(unsafe { Box::from_raw(ptr as *mut Arc<[u8]>) });
+}
+
+/// Call the dropper on drop
+impl Drop for ForeignBuffer {
+    fn drop(&mut self) {
+        (self.dropper)(self.ptr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+    static DROPPED_PTR: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn free_counted(ptr: *mut c_void) {
+        DROPPED.fetch_add(1, Ordering::SeqCst);
+        DROPPED_PTR.store(ptr as usize, Ordering::SeqCst);
+        unsafe { libc::free(ptr) };
+    }
+
+    #[test]
+    fn test_dropper() {
+        let ptr = unsafe { libc::malloc(100) };
+        let mut buf = unsafe { Buffer::from_raw_with_dropper(ptr, 100, free_counted) };
+        buf.fill(3);
+        assert_eq!(buf.len(), 100);
+        assert_eq!(buf.as_buffer().get_raw(), ptr as *const u8);
+        assert!(!buf.as_buffer().is_owned());
+        // Clone is a fresh owned copy
+        let cloned = buf.as_buffer().clone();
+        assert!(cloned.is_owned());
+        assert_ne!(cloned.get_raw(), ptr as *const u8);
+        drop(cloned);
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 0);
+        drop(buf);
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 1);
+        assert_eq!(DROPPED_PTR.load(Ordering::SeqCst), ptr as usize);
+    }
+
+    #[test]
+    fn test_from_arc_slice() {
+        let arc: Arc<[u8]> = Arc::from(b"hello".to_vec());
+        let view = Buffer::from_arc_slice(arc.clone());
+        assert_eq!(Arc::strong_count(&arc), 2);
+        assert_eq!(view.as_buffer().get_raw(), arc.as_ptr());
+        assert_eq!(&view[..], b"hello");
+        assert!(!view.as_buffer().is_owned());
+        assert!(!view.as_buffer().is_mutable());
+        drop(view);
+        assert_eq!(Arc::strong_count(&arc), 1);
+        // Outlives the other references
+        let view = Buffer::from_arc_slice(arc);
+        assert_eq!(&view[..], b"hello");
+        let view = Buffer::from_arc_slice(Arc::from(Vec::new()));
+        assert!(view.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "ForeignBuffer is immutable")]
+    fn test_from_arc_slice_write() {
+        let mut view = Buffer::from_arc_slice(Arc::from(b"hello".to_vec()));
+        view[0] = b'x';
+    }
+}
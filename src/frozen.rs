@@ -0,0 +1,76 @@
+use crate::Buffer;
+use std::{fmt, ops::Deref, sync::Arc};
+
+/// An immutable, owned [Buffer] that is cheap to clone, returned by [Buffer::freeze()].
+///
+/// Clones share the same memory through an `Arc`, so a buffer can be filled once
+/// and then handed out to multiple readers (threads).
+///
+/// ```
+/// use io_buffer::Buffer;
+/// let mut buf = Buffer::alloc(10).unwrap();
+/// buf.copy_from(0, b"0123456789");
+/// let frozen = buf.freeze();
+/// let shared = frozen.clone();
+/// std::thread::spawn(move || assert_eq!(&shared[0..4], b"0123")).join().unwrap();
+/// assert_eq!(frozen.len(), 10);
+/// ```
+///
+/// Can not be written once frozen:
+///
+/// ```compile_fail
+/// use io_buffer::Buffer;
+/// let mut frozen = Buffer::alloc(10).unwrap().freeze();
+/// frozen[0] = 1;
+/// ```
+#[derive(Clone)]
+pub struct FrozenBuffer(Arc<Buffer>);
+
+impl fmt::Debug for FrozenBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FrozenBuffer {
+    #[inline]
+    pub(crate) fn new(buf: Buffer) -> Self {
+        Self(Arc::new(buf))
+    }
+
+    /// Borrow as a [Buffer], for APIs that need one.
+    #[inline(always)]
+    pub fn as_buffer(&self) -> &Buffer {
+        &self.0
+    }
+
+    /// Get back the mutable [Buffer] if this is the only reference,
+    /// otherwise return self.
+    #[inline]
+    pub fn try_unfreeze(self) -> Result<Buffer, Self> {
+        Arc::try_unwrap(self.0).map_err(Self)
+    }
+}
+
+impl Deref for FrozenBuffer {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
+
+impl AsRef<[u8]> for FrozenBuffer {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
+
+impl AsRef<Buffer> for FrozenBuffer {
+    #[inline]
+    fn as_ref(&self) -> &Buffer {
+        &self.0
+    }
+}
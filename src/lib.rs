@@ -3,14 +3,42 @@
 #![doc = include_str!("../README.md")]
 
 mod buffer;
+mod buffer_ref;
+mod crc32c;
+mod fd;
+mod foreign;
+mod frozen;
+#[cfg(target_os = "linux")]
+mod mmap;
+mod pool;
+mod registry;
+#[cfg(feature = "trace-alloc")]
+mod trace;
 mod utils;
+mod write_guard;
+mod xxhash;
 
-pub use buffer::{Buffer, MAX_BUFFER_SIZE};
+#[cfg(any(feature = "debug-poison", doc))]
+pub use buffer::POISON_BYTE;
+pub use buffer::{Buffer, GrowthPolicy, MAX_BUFFER_SIZE};
+pub use buffer_ref::{BufferMut, BufferRef};
+pub use crc32c::{Checksum, ChecksumGuard, Crc32cHasher, VerifyError, crc32c};
+pub use fd::*;
+pub use foreign::ForeignBuffer;
+pub use frozen::FrozenBuffer;
+#[cfg(target_os = "linux")]
+pub use mmap::MmapBuffer;
+pub use pool::{PoolStats, PooledBuffer, SizedBufferPool};
+pub use registry::BufferRegistry;
 pub use utils::*;
+pub use write_guard::WriteGuard;
+#[cfg(any(feature = "xxhash", doc))]
+pub use xxhash::xxhash64;
 
 #[cfg(any(feature = "compress", doc))]
 /// Enabled with feature `compress`
 pub mod compress;
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod test;
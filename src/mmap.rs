@@ -0,0 +1,97 @@
+use crate::Buffer;
+use nix::errno::Errno;
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+    os::fd::RawFd,
+};
+
+/// A [Buffer] view over a memory mapping, unmapped on drop.
+///
+/// Returned by [Buffer::into_memfd()] and [Buffer::map_file_mut()].
+pub struct MmapBuffer {
+    buf: Buffer,
+}
+
+impl fmt::Debug for MmapBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MmapBuffer({:?})", self.buf)
+    }
+}
+
+impl MmapBuffer {
+    /// mmap `len` bytes of `fd` from `offset`, readable and writable, with MAP_SHARED.
+    pub(crate) fn map_shared(fd: RawFd, len: usize, offset: u64) -> Result<Self, Errno> {
+        if len == 0 || len >= crate::MAX_BUFFER_SIZE {
+            return Err(Errno::EINVAL);
+        }
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd,
+                offset as libc::off_t,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(Errno::last());
+        }
+        Ok(Self { buf: Buffer::from_c_ref_mut(ptr, len as i32) })
+    }
+
+    /// Flush the dirty pages back to the file, by `msync(MS_SYNC)`.
+    #[inline]
+    pub fn msync(&self) -> Result<(), Errno> {
+        let res = unsafe {
+            libc::msync(self.buf.get_raw() as *mut libc::c_void, self.buf.capacity(), libc::MS_SYNC)
+        };
+        if res < 0 { Err(Errno::last()) } else { Ok(()) }
+    }
+
+    /// Borrow as a [Buffer], for APIs that need one.
+    #[inline(always)]
+    pub fn as_buffer(&self) -> &Buffer {
+        &self.buf
+    }
+}
+
+impl Deref for MmapBuffer {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        self.buf.as_ref()
+    }
+}
+
+impl DerefMut for MmapBuffer {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.buf.as_mut()
+    }
+}
+
+impl AsRef<[u8]> for MmapBuffer {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self.buf.as_ref()
+    }
+}
+
+impl AsRef<Buffer> for MmapBuffer {
+    #[inline]
+    fn as_ref(&self) -> &Buffer {
+        &self.buf
+    }
+}
+
+/// munmap on drop
+impl Drop for MmapBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.buf.get_raw_mut() as *mut libc::c_void, self.buf.capacity());
+        }
+    }
+}
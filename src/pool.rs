@@ -0,0 +1,361 @@
+use crate::Buffer;
+use nix::errno::Errno;
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+};
+
+/// Statistics of a [SizedBufferPool], see [SizedBufferPool::stats()].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Count of get() served from the free lists
+    pub hits: u64,
+    /// Count of get() that has to allocate
+    pub misses: u64,
+    /// Count of buffers allocated
+    pub allocated: u64,
+    /// Count of buffers freed, because their class is full or the size is not pooled
+    pub freed: u64,
+    /// Total capacity of the buffers in the free lists
+    pub bytes_cached: usize,
+}
+
+struct SizeClass {
+    size: usize,
+    free: Mutex<Vec<Buffer>>,
+}
+
+struct PoolInner {
+    classes: Vec<SizeClass>,
+    depth: usize,
+    aligned: bool,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    allocated: AtomicU64,
+    freed: AtomicU64,
+    bytes_cached: AtomicUsize,
+}
+
+impl PoolInner {
+    fn put(&self, class: Option<usize>, mut buf: Buffer) {
+        // The capacity might be changed, or the buffer replaced by the user
+        let recyclable = buf.is_owned() && (!self.aligned || buf.alloc_align() > 0);
+        if let Some(i) = class.filter(|i| recyclable && self.classes[*i].size == buf.capacity()) {
+            let mut free = self.classes[i].free.lock().unwrap();
+            if free.len() < self.depth {
+                self.bytes_cached.fetch_add(buf.capacity(), Ordering::Relaxed);
+                buf.set_len(buf.capacity());
+                free.push(buf);
+                return;
+            }
+        }
+        self.freed.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// A pool recycling buffers in power-of-two size classes, to avoid allocation
+/// on the hot path when request sizes vary.
+///
+/// [get()](SizedBufferPool::get()) picks the smallest class that fits, the [PooledBuffer] returned
+/// goes back to the free list of its class on drop, unless the class already holds `depth` buffers.
+/// Requests larger than the largest class are allocated without pooling.
+///
+/// The pool is `Send + Sync`, and cheap to clone (clones share the same free lists).
+///
+/// ```
+/// use io_buffer::SizedBufferPool;
+/// let pool = SizedBufferPool::new(512, 65536, 16, true);
+/// {
+///     let buf = pool.get(3000).unwrap();
+///     assert_eq!(buf.len(), 3000);
+///     assert_eq!(buf.capacity(), 4096);
+/// }
+/// let _buf = pool.get(4000).unwrap();
+/// assert_eq!(pool.stats().hits, 1);
+/// ```
+#[derive(Clone)]
+pub struct SizedBufferPool(Arc<PoolInner>);
+
+impl fmt::Debug for SizedBufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SizedBufferPool({:?})", self.stats())
+    }
+}
+
+impl SizedBufferPool {
+    /// Create a pool with size classes `min_size, min_size * 2, ..., max_size`.
+    ///
+    /// `depth`: max count of free buffers kept per class.
+    ///
+    /// `aligned`: allocate by [Buffer::aligned()] instead of [Buffer::alloc()].
+    ///
+    /// # Panic
+    ///
+    /// If min_size or max_size is not power of two, min_size > max_size, max_size >= MAX_BUFFER_SIZE,
+    /// or min_size < 512 when aligned.
+    pub fn new(min_size: usize, max_size: usize, depth: usize, aligned: bool) -> Self {
+        assert!(min_size.is_power_of_two(), "min_size {} is not power of two", min_size);
+        assert!(max_size.is_power_of_two(), "max_size {} is not power of two", max_size);
+        assert!(min_size <= max_size, "min_size {} > max_size {}", min_size, max_size);
+        assert!(max_size < crate::MAX_BUFFER_SIZE, "max_size {} is not supported", max_size);
+        if aligned {
+            assert!(min_size >= 512, "aligned min_size {} must be >= 512", min_size);
+        }
+        let mut classes = Vec::new();
+        let mut size = min_size;
+        while size <= max_size {
+            classes.push(SizeClass { size, free: Mutex::new(Vec::new()) });
+            size *= 2;
+        }
+        Self(Arc::new(PoolInner {
+            classes,
+            depth,
+            aligned,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            allocated: AtomicU64::new(0),
+            freed: AtomicU64::new(0),
+            bytes_cached: AtomicUsize::new(0),
+        }))
+    }
+
+    /// Get a buffer with `len()` set to `size`, and capacity of the smallest class that fits.
+    ///
+    /// **NOTE**: The content is not initialized, and may contain data of previous user.
+    pub fn get(&self, size: usize) -> Result<PooledBuffer, Errno> {
+        let inner = &self.0;
+        let class = inner.classes.iter().position(|c| c.size >= size);
+        if let Some(i) = class {
+            let cached = inner.classes[i].free.lock().unwrap().pop();
+            if let Some(mut buf) = cached {
+                inner.bytes_cached.fetch_sub(buf.capacity(), Ordering::Relaxed);
+                inner.hits.fetch_add(1, Ordering::Relaxed);
+                buf.set_len(size);
+                return Ok(PooledBuffer { buf: Some(buf), class, pool: self.0.clone() });
+            }
+        }
+        inner.misses.fetch_add(1, Ordering::Relaxed);
+        let alloc_size = match class {
+            Some(i) => inner.classes[i].size,
+            None if inner.aligned => size.div_ceil(512) * 512,
+            None => size,
+        };
+        if alloc_size >= crate::MAX_BUFFER_SIZE {
+            return Err(Errno::EINVAL);
+        }
+        let mut buf = if inner.aligned {
+            Buffer::aligned(alloc_size as i32)?
+        } else {
+            Buffer::alloc(alloc_size as i32)?
+        };
+        inner.allocated.fetch_add(1, Ordering::Relaxed);
+        buf.set_len(size);
+        Ok(PooledBuffer { buf: Some(buf), class, pool: self.0.clone() })
+    }
+
+    /// Return the size of each class
+    pub fn class_sizes(&self) -> Vec<usize> {
+        self.0.classes.iter().map(|c| c.size).collect()
+    }
+
+    /// Return a snapshot of the statistics
+    pub fn stats(&self) -> PoolStats {
+        let inner = &self.0;
+        PoolStats {
+            hits: inner.hits.load(Ordering::Relaxed),
+            misses: inner.misses.load(Ordering::Relaxed),
+            allocated: inner.allocated.load(Ordering::Relaxed),
+            freed: inner.freed.load(Ordering::Relaxed),
+            bytes_cached: inner.bytes_cached.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A [Buffer] borrowed from [SizedBufferPool], return to the pool on drop.
+pub struct PooledBuffer {
+    buf: Option<Buffer>,
+    class: Option<usize>,
+    pool: Arc<PoolInner>,
+}
+
+impl PooledBuffer {
+    /// Detach from the pool, the buffer will not be recycled.
+    #[inline]
+    pub fn into_inner(mut self) -> Buffer {
+        self.buf.take().unwrap()
+    }
+}
+
+impl fmt::Debug for PooledBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.deref().fmt(f)
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = Buffer;
+
+    #[inline]
+    fn deref(&self) -> &Buffer {
+        self.buf.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Buffer {
+        self.buf.as_mut().unwrap()
+    }
+}
+
+impl AsRef<[u8]> for PooledBuffer {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self.deref().as_ref()
+    }
+}
+
+impl AsRef<Buffer> for PooledBuffer {
+    #[inline]
+    fn as_ref(&self) -> &Buffer {
+        self.deref()
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(buf) = self.buf.take() {
+            self.pool.put(self.class, buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_class_selection() {
+        let pool = SizedBufferPool::new(512, 65536, 4, true);
+        assert_eq!(pool.class_sizes(), vec![512, 1024, 2048, 4096, 8192, 16384, 32768, 65536]);
+        let buf = pool.get(3000).unwrap();
+        assert_eq!(buf.len(), 3000);
+        assert_eq!(buf.capacity(), 4096);
+        assert!(buf.is_aligned());
+        assert_eq!(pool.get(0).unwrap().capacity(), 512);
+        assert_eq!(pool.get(4096).unwrap().capacity(), 4096);
+        assert_eq!(pool.get(4097).unwrap().capacity(), 8192);
+        // Not pooled
+        let buf = pool.get(100000).unwrap();
+        assert_eq!(buf.len(), 100000);
+        assert!(buf.is_aligned());
+        let pool = SizedBufferPool::new(64, 1024, 4, false);
+        assert_eq!(pool.get(65).unwrap().capacity(), 128);
+    }
+
+    #[test]
+    fn test_stats() {
+        let pool = SizedBufferPool::new(512, 8192, 4, false);
+        let buf = pool.get(3000).unwrap();
+        let ptr = buf.get_raw();
+        assert_eq!(pool.stats(), PoolStats { misses: 1, allocated: 1, ..Default::default() });
+        drop(buf);
+        assert_eq!(pool.stats().bytes_cached, 4096);
+        // Drawing from the same class
+        let buf = pool.get(2049).unwrap();
+        assert_eq!(buf.get_raw(), ptr);
+        assert_eq!(buf.len(), 2049);
+        let stats = pool.stats();
+        assert_eq!((stats.hits, stats.misses, stats.bytes_cached), (1, 1, 0));
+        // Other class misses
+        let buf2 = pool.get(100).unwrap();
+        assert_eq!(pool.stats().misses, 2);
+        drop(buf);
+        drop(buf2);
+        assert_eq!(pool.stats().bytes_cached, 4096 + 512);
+        // Detached buffer is not counted
+        let buf = pool.get(100).unwrap().into_inner();
+        drop(buf);
+        let stats = pool.stats();
+        assert_eq!((stats.hits, stats.freed, stats.bytes_cached), (2, 0, 4096));
+        // Oversize is freed on drop
+        drop(pool.get(10000).unwrap());
+        assert_eq!(pool.stats().freed, 1);
+    }
+
+    #[test]
+    fn test_depth_cap() {
+        let pool = SizedBufferPool::new(512, 4096, 2, false);
+        let bufs: Vec<PooledBuffer> = (0..5).map(|_| pool.get(1000).unwrap()).collect();
+        assert_eq!(pool.stats().allocated, 5);
+        drop(bufs);
+        let stats = pool.stats();
+        assert_eq!(stats.freed, 3);
+        assert_eq!(stats.bytes_cached, 1024 * 2);
+        // Capacity changed by the user, can not return to the class
+        let mut buf = pool.get(1000).unwrap();
+        buf.set_len(1024);
+        buf.push(1).unwrap();
+        drop(buf);
+        assert_eq!(pool.stats().freed, 4);
+    }
+
+    #[test]
+    fn test_put_replaced() {
+        let pool = SizedBufferPool::new(512, 4096, 2, true);
+        // A c ref swapped in must never be recycled
+        let mut data = vec![0u8; 1024];
+        let mut buf = pool.get(1000).unwrap();
+        *buf = unsafe { Buffer::from_slice_mut(&mut data) };
+        drop(buf);
+        // An unaligned buffer is not recycled by an aligned pool
+        let mut buf = pool.get(1000).unwrap();
+        *buf = Buffer::alloc(1024).unwrap();
+        drop(buf);
+        let stats = pool.stats();
+        assert_eq!((stats.freed, stats.bytes_cached), (2, 0));
+    }
+
+    #[test]
+    fn test_as_ref() {
+        fn sum_bytes(s: impl AsRef<[u8]>) -> u32 {
+            s.as_ref().iter().map(|b| *b as u32).sum()
+        }
+        fn buffer_len(b: impl AsRef<Buffer>) -> usize {
+            b.as_ref().len()
+        }
+        let pool = SizedBufferPool::new(512, 4096, 2, false);
+        let mut buf = pool.get(3).unwrap();
+        buf.copy_from(0, &[1, 2, 3]);
+        assert_eq!(sum_bytes(&buf), 6);
+        assert_eq!(buffer_len(&buf), 3);
+    }
+
+    #[test]
+    fn test_send_sync() {
+        fn check<T: Send + Sync>() {}
+        check::<SizedBufferPool>();
+        check::<PooledBuffer>();
+        let pool = SizedBufferPool::new(512, 4096, 8, true);
+        let ths: Vec<_> = (0..4)
+            .map(|_| {
+                let pool = pool.clone();
+                std::thread::spawn(move || {
+                    for i in 0..100 {
+                        let mut buf = pool.get(i * 40).unwrap();
+                        buf.zero();
+                    }
+                })
+            })
+            .collect();
+        for th in ths {
+            th.join().unwrap();
+        }
+        let stats = pool.stats();
+        assert_eq!(stats.hits + stats.misses, 400);
+    }
+}
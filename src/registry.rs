@@ -0,0 +1,83 @@
+use crate::Buffer;
+use nix::errno::Errno;
+
+/// Max buffers io_uring accepts for `IORING_REGISTER_BUFFERS`
+const MAX_REGISTERED: usize = 1 << 14;
+
+/// Collect buffers to register as io_uring fixed buffers, in the `iovec` array shape
+/// `io_uring_register(IORING_REGISTER_BUFFERS)` expects.
+///
+/// The registry takes the buffers, and only lends them out as `&Buffer` or `&mut [u8]`,
+/// so they can not be reallocated, and the pointers stay stable until
+/// [into_buffers()](BufferRegistry::into_buffers()) (after unregistering).
+///
+/// ```
+/// use io_buffer::{Buffer, BufferRegistry};
+/// let mut registry = BufferRegistry::new();
+/// let idx = registry.register(Buffer::aligned(4096).unwrap()).unwrap();
+/// let iovecs = registry.iovecs();
+/// assert_eq!(iovecs[idx as usize].iov_len, 4096);
+/// ```
+#[derive(Default)]
+pub struct BufferRegistry {
+    bufs: Vec<Buffer>,
+    iovecs: Vec<libc::iovec>,
+}
+
+impl BufferRegistry {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a buffer, the whole capacity is registered. Return the fixed buffer index.
+    ///
+    /// Return `EINVAL` if the buffer has no memory (converted from an empty Vec),
+    /// or is not mutable, `EOVERFLOW` if too many buffers.
+    pub fn register(&mut self, mut buf: Buffer) -> Result<u16, Errno> {
+        if buf.capacity() == 0 || !buf.is_mutable() {
+            return Err(Errno::EINVAL);
+        }
+        if self.bufs.len() >= MAX_REGISTERED {
+            return Err(Errno::EOVERFLOW);
+        }
+        buf.set_len(buf.capacity());
+        let idx = self.bufs.len() as u16;
+        self.iovecs.push(buf.as_iovec());
+        self.bufs.push(buf);
+        Ok(idx)
+    }
+
+    /// The `iovec` array to pass to `io_uring_register()`, indexed by the fixed buffer index.
+    #[inline]
+    pub fn iovecs(&self) -> &[libc::iovec] {
+        &self.iovecs
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.bufs.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.bufs.is_empty()
+    }
+
+    #[inline]
+    pub fn get(&self, buf_index: u16) -> Option<&Buffer> {
+        self.bufs.get(buf_index as usize)
+    }
+
+    /// Borrow the content to fill or read, the buffer itself can not be resized.
+    #[inline]
+    pub fn get_mut(&mut self, buf_index: u16) -> Option<&mut [u8]> {
+        self.bufs.get_mut(buf_index as usize).map(|buf| buf.as_mut())
+    }
+
+    /// Give back the buffers, should only be called after unregistering from io_uring.
+    #[inline]
+    pub fn into_buffers(self) -> Vec<Buffer> {
+        self.bufs
+    }
+}
@@ -1,6 +1,11 @@
 use super::*;
+use std::path::PathBuf;
 use std::time::Instant;
 
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("io_buffer_{}_{}", name, std::process::id()))
+}
+
 #[cfg(target_arch = "x86_64")]
 #[test]
 fn test_buffer_size() {
@@ -92,6 +97,30 @@ fn test_copy_and_clean() {
     }
 }
 
+#[test]
+fn test_copy_and_clean_ret() {
+    let src: [u8; 1024] = [1; 1024];
+    let mut buffer = Buffer::alloc(4096).unwrap();
+    buffer.fill(0xff);
+    // Offset in the middle
+    assert_eq!(buffer.copy_and_clean_ret(1024, &src[0..100]), (100, 3996));
+    assert_eq!(&buffer[1024..1124], &src[0..100]);
+    assert!(is_all_zero(&buffer[0..1024]));
+    assert!(is_all_zero(&buffer[1124..]));
+    // Offset zero
+    buffer.fill(0xff);
+    assert_eq!(buffer.copy_and_clean_ret(0, &src[0..100]), (100, 3996));
+    assert!(is_all_zero(&buffer[100..]));
+    // Source larger than the space left
+    let mut buffer = Buffer::alloc(1000).unwrap();
+    buffer.fill(0xff);
+    assert_eq!(buffer.copy_and_clean_ret(100, &src), (900, 100));
+    assert_eq!(&buffer[100..], &src[0..900]);
+    assert!(is_all_zero(&buffer[0..100]));
+    let mut buffer = Buffer::alloc(1024).unwrap();
+    assert_eq!(buffer.copy_and_clean_ret(0, &src), (1024, 0));
+}
+
 #[cfg(feature = "rand")]
 #[test]
 fn test_set_zero() {
@@ -116,6 +145,20 @@ fn test_set_zero() {
     println!("buffer: {}", unsafe { str::from_utf8_unchecked(&buffer) });
 }
 
+#[test]
+fn test_set_zero_ret() {
+    let mut buffer = Buffer::alloc(100).unwrap();
+    buffer.fill(0xff);
+    assert_eq!(buffer.set_zero_ret(20, 20), 20);
+    assert!(is_all_zero(&buffer[20..40]));
+    assert_eq!(buffer[40], 0xff);
+    // Clamped to len()
+    assert_eq!(buffer.set_zero_ret(90, 20), 10);
+    assert_eq!(buffer.set_zero_ret(100, 20), 0);
+    assert_eq!(buffer.set_zero_ret(0, 1000), 100);
+    assert!(is_all_zero(&buffer));
+}
+
 #[cfg(feature = "rand")]
 #[test]
 fn test_set_len() {
@@ -226,3 +269,1982 @@ fn test_c_ref_mutability() {
     assert!(!buffer_ref_const.is_owned());
     rand_buffer(&mut buffer_ref_const);
 }
+
+#[test]
+fn test_trim_trailing_zeros() {
+    let mut buffer = Buffer::aligned(4096).unwrap();
+    buffer.zero();
+    buffer[0] = 1;
+    buffer[99] = 2;
+    buffer.trim_trailing_zeros();
+    assert_eq!(buffer.len(), 100);
+    assert_eq!(buffer.capacity(), 4096);
+    assert_eq!(buffer[99], 2);
+    // Already trimmed
+    buffer.trim_trailing_zeros();
+    assert_eq!(buffer.len(), 100);
+    buffer.zero();
+    buffer.trim_trailing_zeros();
+    assert_eq!(buffer.len(), 0);
+    assert_eq!(buffer.capacity(), 4096);
+}
+
+#[test]
+fn test_content_eq_and_hash() {
+    let mut buffer1 = Buffer::alloc(100).unwrap();
+    buffer1.zero();
+    buffer1.copy_from(0, b"hello");
+    let mut buffer2 = Buffer::aligned(512).unwrap();
+    buffer2.zero();
+    buffer2.copy_from(0, b"hello");
+    assert!(buffer1.content_eq(&buffer2, true));
+    assert_eq!(buffer1.content_hash(true), buffer2.content_hash(true));
+    // Stable across releases, the xxHash64 of b"hello"
+    assert_eq!(buffer1.content_hash(true), 0x26C7827D889F6DA3);
+    assert!(!buffer1.content_eq(&buffer2, false));
+    assert_ne!(buffer1.content_hash(false), buffer2.content_hash(false));
+    buffer2.set_len(100);
+    assert!(buffer1.content_eq(&buffer2, false));
+    assert_eq!(buffer1.content_hash(false), buffer2.content_hash(false));
+    buffer2[50] = 1;
+    assert!(!buffer1.content_eq(&buffer2, true));
+    assert!(!buffer1.content_eq(&buffer2, false));
+}
+
+#[test]
+fn test_copy_to_slice() {
+    let mut buffer = Buffer::alloc(100).unwrap();
+    for i in 0..100 {
+        buffer[i] = i as u8;
+    }
+    let mut dst: [u8; 30] = [0; 30];
+    assert_eq!(buffer.copy_to_slice(10, &mut dst), 30);
+    assert_eq!(&dst[..], &buffer[10..40]);
+    let mut dst: [u8; 30] = [0; 30];
+    assert_eq!(buffer.copy_to_slice(90, &mut dst), 10);
+    assert_eq!(&dst[0..10], &buffer[90..100]);
+    assert_eq!(&dst[10..], &[0; 20]);
+    assert_eq!(buffer.copy_to_slice(100, &mut dst), 0);
+}
+
+#[test]
+fn test_set_len_boundary() {
+    let mut buffer = Buffer::alloc(100).unwrap();
+    // Must not exceed the capacity, regardless of the mutable bit
+    let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| buffer.set_len(101)));
+    assert!(r.is_err());
+    let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        buffer.set_len(MAX_BUFFER_SIZE - 1)
+    }));
+    assert!(r.is_err());
+    let r =
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| buffer.set_len(MAX_BUFFER_SIZE)));
+    assert!(r.is_err());
+    assert!(buffer.is_owned());
+    assert!(buffer.is_mutable());
+    assert_eq!(buffer.len(), 100);
+    assert_eq!(buffer.capacity(), 100);
+
+    // A c ref with the max capacity, the memory is never accessed
+    let mut buffer_ref =
+        Buffer::from_c_ref_mut(buffer.get_raw_mut() as *mut libc::c_void, i32::MAX);
+    buffer_ref.set_len(MAX_BUFFER_SIZE - 1);
+    assert_eq!(buffer_ref.len(), MAX_BUFFER_SIZE - 1);
+    assert_eq!(buffer_ref.capacity(), MAX_BUFFER_SIZE - 1);
+    assert!(!buffer_ref.is_owned());
+    assert!(buffer_ref.is_mutable());
+    buffer_ref.set_len(0);
+    assert_eq!(buffer_ref.len(), 0);
+    assert!(!buffer_ref.is_owned());
+}
+
+#[test]
+#[should_panic(expected = "is not supported")]
+fn test_set_len_max() {
+    let mut buffer = Buffer::alloc(100).unwrap();
+    buffer.set_len(MAX_BUFFER_SIZE);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_aligned_for() {
+    use std::os::fd::AsRawFd;
+    let path = std::env::temp_dir().join(format!("io_buffer_aligned_for_{}", std::process::id()));
+    let file = std::fs::File::create(&path).unwrap();
+    // Not a block device
+    assert_eq!(device_block_size(file.as_raw_fd()), Err(nix::errno::Errno::ENOTTY));
+    assert_eq!(device_physical_block_size(file.as_raw_fd()), Err(nix::errno::Errno::ENOTTY));
+    assert!(Buffer::aligned_for(file.as_raw_fd(), 4096).is_err());
+    drop(file);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_from_slice() {
+    let mut arr: [u8; 64] = [0; 64];
+    {
+        let mut buffer = unsafe { Buffer::from_slice_mut(&mut arr) };
+        assert!(!buffer.is_owned());
+        assert!(buffer.is_mutable());
+        assert_eq!(buffer.len(), 64);
+        assert_eq!(buffer.capacity(), 64);
+        buffer.copy_from(10, b"hello");
+        // Drop frees nothing
+    }
+    assert_eq!(&arr[10..15], b"hello");
+    arr[0] = 1;
+    {
+        let buffer = unsafe { Buffer::from_slice_ref(&arr[..20]) };
+        assert!(!buffer.is_owned());
+        assert!(!buffer.is_mutable());
+        assert_eq!(buffer.len(), 20);
+        assert_eq!(buffer[0], 1);
+        assert_eq!(&buffer[10..15], b"hello");
+    }
+    let empty: [u8; 0] = [];
+    let buffer = unsafe { Buffer::from_slice_ref(&empty) };
+    assert_eq!(buffer.len(), 0);
+    assert_eq!(arr[0], 1);
+}
+
+#[test]
+fn test_ptr_range() {
+    let mut buffer = Buffer::alloc(100).unwrap();
+    let range = buffer.as_ptr_range();
+    assert_eq!(range.start, buffer.get_raw());
+    assert_eq!(range.end as usize - range.start as usize, 100);
+    let range = buffer.as_mut_ptr_range();
+    assert_eq!(range.end as usize - range.start as usize, 100);
+    buffer.set_len(0);
+    let range = buffer.as_ptr_range();
+    assert_eq!(range.start, range.end);
+    assert_eq!(range.start, buffer.get_raw());
+}
+
+#[test]
+fn test_ptr_range_ffi() {
+    /// A C-style API taking the begin / end pair
+    extern "C" fn sum_range(begin: *const u8, end: *const u8) -> u64 {
+        let mut sum = 0;
+        let mut p = begin;
+        while p < end {
+            sum += unsafe { *p } as u64;
+            p = unsafe { p.add(1) };
+        }
+        sum
+    }
+
+    let mut buffer = Buffer::from((1..=100u8).collect::<Vec<u8>>());
+    let range = buffer.as_ptr_range();
+    assert_eq!(sum_range(range.start, range.end), 5050);
+    buffer.set_len(10);
+    let range = buffer.as_ptr_range();
+    assert_eq!(range.end as usize - range.start as usize, buffer.len());
+    assert_eq!(sum_range(range.start, range.end), 55);
+    let range = buffer.as_mut_ptr_range();
+    unsafe { *range.start = 0 };
+    assert_eq!(buffer[0], 0);
+}
+
+#[test]
+fn test_iovec() {
+    use std::os::fd::AsRawFd;
+    let path = temp_path("iovec");
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+    let mut bufs = Vec::new();
+    for i in 1..4 {
+        let mut buf = Buffer::alloc(i * 100).unwrap();
+        for b in buf.iter_mut() {
+            *b = i as u8;
+        }
+        bufs.push(buf);
+    }
+    let iovecs = Buffer::as_iovecs(&bufs);
+    assert_eq!(iovecs.len(), 3);
+    assert_eq!(iovecs[1].iov_base as *const u8, bufs[1].get_raw());
+    assert_eq!(iovecs[1].iov_len, 200);
+    let res = unsafe { libc::pwritev(file.as_raw_fd(), iovecs.as_ptr(), 3, 0) };
+    assert_eq!(res, 600);
+
+    let mut read_bufs = [Buffer::alloc(300).unwrap(), Buffer::alloc(300).unwrap()];
+    let iovecs: Vec<libc::iovec> = read_bufs.iter_mut().map(|buf| buf.as_iovec_mut()).collect();
+    let res = unsafe { libc::preadv(file.as_raw_fd(), iovecs.as_ptr(), 2, 0) };
+    assert_eq!(res, 600);
+    assert_eq!(&read_bufs[0][0..100], &bufs[0][..]);
+    assert_eq!(&read_bufs[0][100..300], &bufs[1][..]);
+    assert_eq!(&read_bufs[1][..], &bufs[2][..]);
+    drop(file);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_with_capacity() {
+    let mut buffer = Buffer::with_capacity(100).unwrap();
+    assert_eq!(buffer.len(), 0);
+    assert_eq!(buffer.capacity(), 100);
+    assert!(buffer.is_owned());
+    assert!(buffer.is_mutable());
+    buffer.set_len(100);
+    assert_eq!(buffer.len(), 100);
+    let buffer = Buffer::aligned_with_capacity(4096).unwrap();
+    assert_eq!(buffer.len(), 0);
+    assert_eq!(buffer.capacity(), 4096);
+    assert!(buffer.is_aligned());
+    assert!(buffer.is_owned());
+    assert!(buffer.is_mutable());
+}
+
+#[test]
+fn test_alloc_with_margin() {
+    let mut buffer = Buffer::alloc_with_margin(1000, 24).unwrap();
+    assert_eq!(buffer.len(), 1000);
+    assert_eq!(buffer.capacity(), 1024);
+    assert_eq!(buffer.margin(), 24);
+    assert!(buffer.is_owned());
+    assert!(buffer.is_mutable());
+    buffer.set_len(1024);
+    assert_eq!(buffer.margin(), 0);
+    let buffer = Buffer::alloc_with_margin(1000, 0).unwrap();
+    assert_eq!(buffer.margin(), 0);
+    assert_eq!(Buffer::alloc_with_margin(i32::MAX, 1).unwrap_err(), nix::errno::Errno::EINVAL);
+}
+
+#[test]
+fn test_reserve_with() {
+    let mut buffer = Buffer::with_capacity(100).unwrap();
+    buffer.set_len(100);
+    buffer.reserve_with(GrowthPolicy::OneAndHalf, 1).unwrap();
+    assert_eq!(buffer.capacity(), 150);
+    buffer.set_len(150);
+    buffer.reserve_with(GrowthPolicy::Double, 1).unwrap();
+    assert_eq!(buffer.capacity(), 300);
+    // Enough capacity
+    buffer.reserve_with(GrowthPolicy::Double, 150).unwrap();
+    assert_eq!(buffer.capacity(), 300);
+    // Larger than the growth factor
+    buffer.reserve_with(GrowthPolicy::OneAndHalf, 1000).unwrap();
+    assert_eq!(buffer.capacity(), 1150);
+    // Push under the policy
+    let mut buffer = Buffer::with_capacity(8).unwrap();
+    for i in 0..1000 {
+        buffer.reserve_with(GrowthPolicy::OneAndHalf, 1).unwrap();
+        buffer.push(i as u8).unwrap();
+    }
+    assert!(buffer.capacity() >= 1000 && buffer.capacity() < 1500);
+    // Aligned buffer stays aligned
+    let mut buffer = Buffer::aligned(1024).unwrap();
+    buffer.reserve_with(GrowthPolicy::OneAndHalf, 1).unwrap();
+    assert_eq!(buffer.capacity(), 1536);
+    assert_eq!(buffer.alloc_align(), 512);
+    assert_eq!(
+        buffer.reserve_with(GrowthPolicy::Double, usize::MAX),
+        Err(nix::errno::Errno::EOVERFLOW)
+    );
+    let mut arr = [0u8; 4];
+    let mut buffer = unsafe { Buffer::from_slice_mut(&mut arr) };
+    assert_eq!(buffer.reserve_with(GrowthPolicy::Double, 1), Err(nix::errno::Errno::EINVAL));
+}
+
+#[test]
+fn test_push() {
+    let mut buffer = Buffer::with_capacity(1).unwrap();
+    for i in 0..1000 {
+        buffer.push(i as u8).unwrap();
+    }
+    assert_eq!(buffer.len(), 1000);
+    assert!(buffer.capacity() >= 1000 && buffer.capacity() < 2000);
+    for i in 0..1000 {
+        assert_eq!(buffer[i], i as u8);
+    }
+    // aligned buffer stays aligned
+    let mut buffer = Buffer::aligned(512).unwrap();
+    buffer.zero();
+    buffer.push(1).unwrap();
+    assert!(buffer.is_aligned());
+    assert_eq!(buffer.len(), 513);
+    assert_eq!(buffer.capacity(), 1024);
+    assert!(is_all_zero(&buffer[0..512]));
+    assert_eq!(buffer[512], 1);
+    // from Vec
+    let mut buffer: Buffer = Vec::new().into();
+    buffer.push(1).unwrap();
+    assert_eq!(&buffer[..], &[1]);
+    // c ref
+    let mut arr: [u8; 4] = [0; 4];
+    let mut buffer = unsafe { Buffer::from_slice_mut(&mut arr) };
+    assert_eq!(buffer.push(1), Err(nix::errno::Errno::EINVAL));
+    // c ref with spare capacity
+    let mut buffer = unsafe { Buffer::from_slice_mut(&mut arr) };
+    buffer.set_len(2);
+    assert_eq!(buffer.push(1), Err(nix::errno::Errno::EINVAL));
+    assert_eq!(buffer.len(), 2);
+}
+
+#[test]
+fn test_alloc_align() {
+    let buffer = Buffer::aligned(512).unwrap();
+    assert_eq!(buffer.alloc_align(), 512);
+    assert_eq!(buffer.capacity(), 512);
+    assert!(buffer.is_mutable());
+    let mut buffer = Buffer::aligned_by(4096, 4096).unwrap();
+    assert_eq!(buffer.alloc_align(), 4096);
+    assert_eq!(buffer.capacity(), 4096);
+    buffer.zero();
+    buffer.push(1).unwrap();
+    assert_eq!(buffer.alloc_align(), 4096);
+    assert_eq!(buffer.capacity(), 8192);
+    assert_eq!(buffer.get_raw() as usize % 4096, 0);
+    assert!(is_all_zero(&buffer[0..4096]));
+    assert_eq!(buffer[4096], 1);
+    let v: Vec<u8> = buffer.into();
+    assert_eq!(v.len(), 4097);
+    assert_eq!(v.capacity(), 8192);
+    // The alignment of a malloc buffer is never inferred from the address
+    let mut ptr: *mut libc::c_void = std::ptr::null_mut();
+    assert_eq!(unsafe { libc::posix_memalign(&mut ptr, 512, 1024) }, 0);
+    let mut buffer = unsafe { Buffer::from_malloc_raw(ptr, 1024, 1024) };
+    assert!(buffer.is_aligned());
+    assert_eq!(buffer.alloc_align(), 0);
+    buffer.try_reserve_exact(1).unwrap();
+    assert_eq!(buffer.capacity(), 1025);
+    assert_eq!(Buffer::alloc(512).unwrap().alloc_align(), 0);
+    let mut data = [0u8; 16];
+    assert_eq!(unsafe { Buffer::from_slice_mut(&mut data) }.alloc_align(), 0);
+}
+
+#[test]
+fn test_buffer_ref() {
+    let data: [u8; 16] = [1; 16];
+    let buf_ref = BufferRef::from_slice(&data);
+    assert_eq!(&buf_ref[..], &data[..]);
+    let buffer: &Buffer = buf_ref.as_buffer();
+    assert!(!buffer.is_owned());
+    assert!(!buffer.is_mutable());
+    assert_eq!(buffer.len(), 16);
+
+    let mut data: [u8; 16] = [0; 16];
+    {
+        let mut buf_mut = BufferMut::from_slice(&mut data);
+        assert!(buf_mut.as_buffer().is_mutable());
+        buf_mut[3] = 3;
+    }
+    assert_eq!(data[3], 3);
+}
+
+#[test]
+fn test_keep_prefix() {
+    let mut buffer = Buffer::alloc(4096).unwrap();
+    for i in 0..4096 {
+        buffer[i] = i as u8;
+    }
+    buffer.keep_prefix(100).unwrap();
+    assert_eq!(buffer.len(), 100);
+    assert_eq!(buffer.capacity(), 100);
+    for i in 0..100 {
+        assert_eq!(buffer[i], i as u8);
+    }
+    buffer.keep_prefix(200).unwrap();
+    assert_eq!(buffer.len(), 100);
+    assert_eq!(buffer.capacity(), 100);
+
+    let mut buffer = Buffer::aligned(8192).unwrap();
+    buffer.zero();
+    buffer[99] = 1;
+    buffer.keep_prefix(100).unwrap();
+    assert!(buffer.is_aligned());
+    assert_eq!(buffer.len(), 100);
+    assert_eq!(buffer.capacity(), 512);
+    assert_eq!(buffer[99], 1);
+    // Already at the rounded capacity, no realloc
+    let ptr = buffer.get_raw();
+    buffer.keep_prefix(10).unwrap();
+    assert_eq!(buffer.get_raw(), ptr);
+    assert_eq!(buffer.len(), 10);
+    assert_eq!(buffer.capacity(), 512);
+
+    let mut buffer = Buffer::aligned_by(8192, 4096).unwrap();
+    buffer.keep_prefix(100).unwrap();
+    assert_eq!(buffer.capacity(), 4096);
+    assert_eq!(buffer.get_raw() as usize % 4096, 0);
+
+    let mut data: [u8; 64] = [0; 64];
+    let mut buffer = unsafe { Buffer::from_slice_mut(&mut data) };
+    buffer.keep_prefix(10).unwrap();
+    assert_eq!(buffer.len(), 10);
+    assert_eq!(buffer.capacity(), 64);
+}
+
+#[test]
+fn test_find_any() {
+    let mut buffer = Buffer::alloc(32).unwrap();
+    buffer.copy_from(0, b"key=value;next:line\nlast_line___");
+    assert_eq!(buffer.find_any(b";:\n"), Some((9, b';')));
+    assert_eq!(buffer.find_any(b"\n:"), Some((14, b':')));
+    assert_eq!(buffer.find_any(b"\n"), Some((19, b'\n')));
+    assert_eq!(buffer.find_any(b"#"), None);
+    assert_eq!(buffer.find_any(b""), None);
+    buffer.set_len(0);
+    assert_eq!(buffer.find_any(b"k"), None);
+}
+
+#[test]
+fn test_as_ref_wrappers() {
+    fn sum_bytes(s: impl AsRef<[u8]>) -> u32 {
+        s.as_ref().iter().map(|b| *b as u32).sum()
+    }
+    fn buffer_len(b: impl AsRef<Buffer>) -> usize {
+        b.as_ref().len()
+    }
+    let data: [u8; 4] = [1, 2, 3, 4];
+    let mut buffer = Buffer::alloc(4).unwrap();
+    buffer.copy_from(0, &data);
+    let buf_ref = BufferRef::from_slice(&data);
+    assert_eq!(sum_bytes(&buffer), 10);
+    assert_eq!(sum_bytes(&buf_ref), 10);
+    assert_eq!(buffer_len(&buffer), 4);
+    assert_eq!(buffer_len(&buf_ref), 4);
+    assert_eq!(sum_bytes(buffer), 10);
+}
+
+#[test]
+fn test_byte_histogram() {
+    let mut buffer = Buffer::alloc(4099).unwrap();
+    buffer.zero();
+    let histogram = buffer.byte_histogram();
+    assert_eq!(histogram[0], 4099);
+    assert_eq!(histogram.iter().sum::<u64>(), 4099);
+    assert_eq!(buffer.shannon_entropy(), 0.0);
+    for i in 0..4099 {
+        buffer[i] = (i % 256) as u8;
+    }
+    let histogram = buffer.byte_histogram();
+    assert_eq!(histogram[0], 17);
+    assert_eq!(histogram[2], 17);
+    assert_eq!(histogram[3], 16);
+    assert_eq!(histogram.iter().sum::<u64>(), 4099);
+
+    let mut buffer = Buffer::alloc(1024 * 1024).unwrap();
+    fastrand::fill(&mut buffer);
+    let entropy = buffer.shannon_entropy();
+    assert!(entropy > 7.9 && entropy <= 8.0, "entropy {}", entropy);
+    buffer.set_len(0);
+    assert_eq!(buffer.shannon_entropy(), 0.0);
+}
+
+#[test]
+fn test_copy_fd_to_fd() {
+    use std::io::{Read, Seek, Write};
+    use std::os::fd::AsRawFd;
+    // Serialize with the failpoint tests
+    #[cfg(feature = "fail")]
+    let _scenario = fail::FailScenario::setup();
+    let src_path = temp_path("copy_src");
+    let dst_path = temp_path("copy_dst");
+    let mut content = Buffer::alloc(10000).unwrap();
+    fastrand::fill(&mut content);
+    std::fs::File::create(&src_path).unwrap().write_all(&content).unwrap();
+    let src = std::fs::File::open(&src_path).unwrap();
+    let mut dst = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&dst_path)
+        .unwrap();
+    let mut buf = Buffer::aligned(4096).unwrap();
+    let copied = copy_fd_to_fd(src.as_raw_fd(), dst.as_raw_fd(), 9000, &mut buf).unwrap();
+    assert_eq!(copied, 9000);
+    // Reaching EOF
+    let copied = copy_fd_to_fd(src.as_raw_fd(), dst.as_raw_fd(), 9000, &mut buf).unwrap();
+    assert_eq!(copied, 1000);
+    let mut copy = Vec::new();
+    dst.rewind().unwrap();
+    dst.read_to_end(&mut copy).unwrap();
+    assert_eq!(&copy[..], &content[..]);
+    // Immutable or empty buffer
+    let data = [0u8; 16];
+    let mut buf_const = unsafe { Buffer::from_slice_ref(&data) };
+    let e = copy_fd_to_fd(src.as_raw_fd(), dst.as_raw_fd(), 9000, &mut buf_const).unwrap_err();
+    assert_eq!(e, nix::errno::Errno::EINVAL);
+    let mut buf_empty = Buffer::from(Vec::new());
+    let e = copy_fd_to_fd(src.as_raw_fd(), dst.as_raw_fd(), 9000, &mut buf_empty).unwrap_err();
+    assert_eq!(e, nix::errno::Errno::EINVAL);
+    std::fs::remove_file(&src_path).unwrap();
+    std::fs::remove_file(&dst_path).unwrap();
+}
+
+#[test]
+fn test_memchr() {
+    let mut buffer = Buffer::from(b"\nfirst line\nsecond line\nthird\n".to_vec());
+    assert_eq!(buffer.memchr(b'\n'), Some(0));
+    assert_eq!(buffer.memrchr(b'\n'), Some(29));
+    assert_eq!(buffer.memchr(b's'), Some(4));
+    assert_eq!(buffer.memrchr(b's'), Some(12));
+    assert_eq!(buffer.memchr(b'#'), None);
+    assert_eq!(buffer.memrchr(b'#'), None);
+    buffer.set_len(29);
+    assert_eq!(buffer.memrchr(b'\n'), Some(23));
+    buffer.set_len(0);
+    assert_eq!(buffer.memchr(b'\n'), None);
+    assert_eq!(buffer.memrchr(b'\n'), None);
+}
+
+#[test]
+fn test_freeze() {
+    let mut buffer = Buffer::aligned(4096).unwrap();
+    buffer.copy_from(0, b"frozen");
+    buffer.set_len(6);
+    let frozen = buffer.freeze();
+    assert_eq!(&frozen[..], b"frozen");
+    assert!(frozen.as_buffer().is_aligned());
+    let shared = frozen.clone();
+    assert_eq!(shared.as_ptr(), frozen.as_ptr());
+    let frozen = frozen.try_unfreeze().unwrap_err();
+    drop(shared);
+    let mut buffer = frozen.try_unfreeze().unwrap();
+    buffer[0] = b'F';
+    assert_eq!(&buffer[..], b"Frozen");
+}
+
+#[cfg(all(feature = "debug-poison", debug_assertions))]
+#[test]
+fn test_debug_poison() {
+    let buffer = Buffer::alloc(100).unwrap();
+    assert!(buffer.iter().all(|b| *b == POISON_BYTE));
+    let buffer = Buffer::aligned(4096).unwrap();
+    assert!(buffer.iter().all(|b| *b == POISON_BYTE));
+    let mut buffer = Buffer::with_capacity(10).unwrap();
+    for i in 0..100 {
+        buffer.push(i).unwrap();
+    }
+    let cap = buffer.capacity();
+    buffer.set_len(cap);
+    assert!(buffer[100..].iter().all(|b| *b == POISON_BYTE));
+}
+
+#[test]
+#[should_panic(expected = "overflows")]
+fn test_set_zero_overflow() {
+    let mut buffer = Buffer::alloc(100).unwrap();
+    // Would wrap into a small end without the check
+    buffer.set_zero(10, usize::MAX - 5);
+}
+
+#[test]
+#[should_panic(expected = "out of buffer size")]
+fn test_set_zero_offset_overflow() {
+    let mut buffer = Buffer::alloc(100).unwrap();
+    buffer.set_zero(101, 0);
+}
+
+#[test]
+#[should_panic(expected = "must be >= 0")]
+fn test_c_ref_negative_size() {
+    let mut buffer = Buffer::alloc(100).unwrap();
+    // A negative size must not turn into the mutable flag bit
+    let _ = Buffer::from_c_ref_mut(buffer.get_raw_mut() as *mut libc::c_void, -1);
+}
+
+#[test]
+fn test_set_len_keeps_flags() {
+    let mut buffer = Buffer::alloc(100).unwrap();
+    buffer.set_len(0);
+    assert!(buffer.is_owned());
+    assert!(buffer.is_mutable());
+    let r = std::panic::catch_unwind(move || {
+        buffer.set_len(MAX_BUFFER_SIZE | 10);
+    });
+    assert!(r.is_err());
+}
+
+#[test]
+fn test_aligned_subrange() {
+    let mut buffer = Buffer::aligned(4096).unwrap();
+    assert_eq!(buffer.aligned_subrange(512), Some((0, 4096)));
+    buffer.set_len(1000);
+    assert_eq!(buffer.aligned_subrange(512), Some((0, 512)));
+    buffer.set_len(100);
+    assert_eq!(buffer.aligned_subrange(512), None);
+    let buffer = Buffer::aligned(4096).unwrap();
+    let sub = unsafe { Buffer::from_slice_ref(&buffer[100..4000]) };
+    // 412..3996 relative to the sub buffer
+    assert_eq!(sub.aligned_subrange(512), Some((412, 412 + 512 * 6)));
+    let sub = unsafe { Buffer::from_slice_ref(&buffer[100..600]) };
+    assert_eq!(sub.aligned_subrange(512), None);
+    assert_eq!(sub.aligned_subrange(1), Some((0, 500)));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_equals_file_region() {
+    use std::io::Write;
+    use std::os::fd::AsRawFd;
+    let path = temp_path("equals_file_region");
+    let mut file = std::fs::File::create(&path).unwrap();
+    let content: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+    file.write_all(&content).unwrap();
+    let file = std::fs::File::open(&path).unwrap();
+    let fd = file.as_raw_fd();
+    let buffer = Buffer::from(content[1000..2000].to_vec());
+    assert!(buffer.equals_file_region(fd, 1000).unwrap());
+    assert!(!buffer.equals_file_region(fd, 1001).unwrap());
+    // The file is shorter than the region
+    assert!(!buffer.equals_file_region(fd, 2500).unwrap());
+    assert!(unsafe { Buffer::from_slice_ref(&[]) }.equals_file_region(fd, 0).unwrap());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_pread_verified() {
+    use nix::errno::Errno;
+    use std::io::Write;
+    use std::os::fd::AsRawFd;
+    let path = temp_path("pread_verified");
+    let mut file = std::fs::File::create(&path).unwrap();
+    let content: Vec<u8> = (0..2100u32).map(|i| (i % 251) as u8).collect();
+    file.write_all(&content[0..100]).unwrap();
+    file.write_all(&crc32c(&content[0..100]).to_le_bytes()).unwrap();
+    file.write_all(&content[100..2100]).unwrap();
+    file.write_all(&crc32c(&content[100..2100]).to_le_bytes()).unwrap();
+    let file = std::fs::File::open(&path).unwrap();
+    let fd = file.as_raw_fd();
+    let mut buffer = Buffer::alloc(4096).unwrap();
+    buffer.pread_verified::<Crc32cHasher>(fd, 104, 2000).unwrap();
+    assert_eq!(&buffer[..], &content[100..2100]);
+    // Mismatch, the data is not exposed
+    let mut trailer = [content[2099], 0, 0, 0];
+    trailer[1..].copy_from_slice(&crc32c(&content[100..2100]).to_le_bytes()[0..3]);
+    let e = buffer.pread_verified::<Crc32cHasher>(fd, 104, 1999).unwrap_err();
+    assert_eq!(
+        e,
+        VerifyError::ChecksumMismatch {
+            computed: crc32c(&content[100..2099]) as u64,
+            stored: u32::from_le_bytes(trailer) as u64,
+        }
+    );
+    assert_eq!(buffer.len(), 0);
+    // The first trailer
+    buffer.pread_verified::<Crc32cHasher>(fd, 0, 100).unwrap();
+    assert_eq!(&buffer[..], &content[0..100]);
+    // EOF before the trailer
+    assert_eq!(buffer.pread_verified::<Crc32cHasher>(fd, 106, 2000), Err(Errno::EIO.into()));
+    // Capacity too small
+    let mut small = Buffer::alloc(2003).unwrap();
+    assert_eq!(small.pread_verified::<Crc32cHasher>(fd, 104, 2000), Err(Errno::EINVAL.into()));
+    // Digest wider than the trailer supports
+    struct Wide;
+    impl Checksum for Wide {
+        const SIZE: usize = 16;
+        fn new() -> Self {
+            Wide
+        }
+        fn update(&mut self, _data: &[u8]) {}
+        fn finalize(&self) -> u64 {
+            0
+        }
+    }
+    assert_eq!(buffer.pread_verified::<Wide>(fd, 0, 100), Err(Errno::EINVAL.into()));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_equals_file_region_empty_vec() {
+    use std::os::fd::AsRawFd;
+    let path = temp_path("equals_file_region_empty_vec");
+    let file = std::fs::File::create(&path).unwrap();
+    // Never touches the dangling pointer of an empty Vec
+    let buffer = Buffer::from(Vec::new());
+    assert!(buffer.equals_file_region(file.as_raw_fd(), 0).unwrap());
+    assert!(buffer.equals_file_region(file.as_raw_fd(), 4096).unwrap());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_equals_file_region_direct() {
+    use std::io::Write;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::OpenOptionsExt;
+    let path = temp_path("equals_file_region_direct");
+    let content: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+    std::fs::File::create(&path).unwrap().write_all(&content).unwrap();
+    // tmpfs does not support O_DIRECT
+    let Ok(file) = std::fs::OpenOptions::new().read(true).custom_flags(libc::O_DIRECT).open(&path)
+    else {
+        std::fs::remove_file(&path).unwrap();
+        return;
+    };
+    let fd = file.as_raw_fd();
+    let buffer = Buffer::from(content[1000..5000].to_vec());
+    assert!(buffer.equals_file_region(fd, 1000).unwrap());
+    assert!(!buffer.equals_file_region(fd, 1001).unwrap());
+    let buffer = Buffer::from(content[9000..].to_vec());
+    assert!(buffer.equals_file_region(fd, 9000).unwrap());
+    assert!(!buffer.equals_file_region(fd, 9001).unwrap());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+struct TestHeader {
+    magic: u32,
+    flags: u16,
+    kind: u16,
+    offset: u64,
+}
+
+#[test]
+fn test_read_write_struct() {
+    let mut buffer = Buffer::alloc(100).unwrap();
+    buffer.zero();
+    let header = TestHeader { magic: 0xdeadbeef, flags: 3, kind: 7, offset: 1 << 40 };
+    // Unaligned offset
+    unsafe { buffer.write_struct(3, &header) };
+    assert_eq!(&buffer[3..7], &0xdeadbeef_u32.to_ne_bytes());
+    assert_eq!(&buffer[11..19], &(1u64 << 40).to_ne_bytes());
+    assert_eq!(unsafe { buffer.read_struct::<TestHeader>(3) }, header);
+    assert_eq!(unsafe { buffer.read_struct::<u16>(7) }, 3);
+    unsafe { buffer.write_struct(100 - 16, &header) };
+    assert_eq!(unsafe { buffer.read_struct::<TestHeader>(100 - 16) }, header);
+}
+
+#[test]
+#[should_panic(expected = "out of buffer size")]
+fn test_read_struct_out_of_range() {
+    let buffer = Buffer::alloc(100).unwrap();
+    let _ = unsafe { buffer.read_struct::<TestHeader>(90) };
+}
+
+#[test]
+fn test_empty_vec() {
+    // Dropping must not free the dangling pointer of an empty Vec
+    let buffer = Buffer::from(Vec::<u8>::new());
+    assert!(buffer.is_owned());
+    assert_eq!(buffer.len(), 0);
+    assert_eq!(buffer.capacity(), 0);
+    drop(buffer);
+    let buffer = Buffer::from(Vec::<u8>::new());
+    let cloned = buffer.clone();
+    assert_eq!(cloned.capacity(), 0);
+    let v: Vec<u8> = buffer.into();
+    assert!(v.is_empty());
+    assert_eq!(v.capacity(), 0);
+    let v: Vec<u8> = cloned.into();
+    assert!(v.is_empty());
+    // Round-trips an empty buffer with capacity
+    let mut buffer = Buffer::alloc(10).unwrap();
+    buffer.set_len(0);
+    let v: Vec<u8> = buffer.into();
+    assert!(v.is_empty());
+    assert_eq!(v.capacity(), 10);
+}
+
+#[test]
+fn test_replace_range() {
+    let mut buffer = Buffer::from(b"hello world".to_vec());
+    // Same length
+    buffer.replace_range(0, 5, b"HELLO").unwrap();
+    assert_eq!(&buffer[..], b"HELLO world");
+    // Shorter
+    buffer.replace_range(6, 5, b"rs").unwrap();
+    assert_eq!(&buffer[..], b"HELLO rs");
+    // Longer, grows the owned buffer
+    buffer.replace_range(5, 1, b", dear ").unwrap();
+    assert_eq!(&buffer[..], b"HELLO, dear rs");
+    // Insert and remove
+    buffer.replace_range(0, 0, b">").unwrap();
+    buffer.replace_range(buffer.len(), 0, b"<").unwrap();
+    assert_eq!(&buffer[..], b">HELLO, dear rs<");
+    buffer.replace_range(1, 7, b"").unwrap();
+    assert_eq!(&buffer[..], b">dear rs<");
+    assert_eq!(buffer.replace_range(5, 10, b"x"), Err(nix::errno::Errno::EINVAL));
+    assert_eq!(buffer.replace_range(usize::MAX, 1, b"x"), Err(nix::errno::Errno::EINVAL));
+    // c ref can not grow
+    let mut data = *b"abcd";
+    let mut buf_ref = unsafe { Buffer::from_slice_mut(&mut data) };
+    buf_ref.replace_range(1, 2, b"X").unwrap();
+    assert_eq!(&buf_ref[..], b"aXd");
+    assert_eq!(buf_ref.replace_range(0, 1, b"long"), Err(nix::errno::Errno::EINVAL));
+    let mut buf_const = unsafe { Buffer::from_slice_ref(b"abcd") };
+    assert_eq!(buf_const.replace_range(0, 1, b"x"), Err(nix::errno::Errno::EINVAL));
+}
+
+#[test]
+fn test_iter() {
+    let mut buffer = Buffer::from(vec![1u8, 2, 3, 4]);
+    buffer.set_len(3);
+    let mut sum = 0;
+    for b in &buffer {
+        sum += *b;
+    }
+    assert_eq!(sum, 6);
+    assert_eq!(buffer.iter().rev().copied().collect::<Vec<u8>>(), vec![3, 2, 1]);
+    for b in &mut buffer {
+        *b += 1;
+    }
+    buffer.iter_mut().for_each(|b| *b *= 10);
+    assert_eq!(&buffer[..], &[20, 30, 40]);
+    let buffer = Buffer::from(Vec::new());
+    assert_eq!(buffer.iter().count(), 0);
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic]
+fn test_iter_mut_const() {
+    let mut buffer = unsafe { Buffer::from_slice_ref(b"abc") };
+    for b in &mut buffer {
+        *b += 1;
+    }
+}
+
+#[cfg(feature = "trace-alloc")]
+#[test]
+fn test_trace_alloc() {
+    let buffer = Buffer::alloc(100).unwrap();
+    let ptr = buffer.get_raw() as *const libc::c_void;
+    let id = trace::live_id(ptr).unwrap();
+    let buffer2 = Buffer::aligned(4096).unwrap();
+    let id2 = trace::live_id(buffer2.get_raw() as *const libc::c_void).unwrap();
+    assert!(id2 > id);
+    drop(buffer);
+    // The ptr might be reused by other threads
+    assert_ne!(trace::live_id(ptr), Some(id));
+    let mut buffer = Buffer::from(vec![1u8; 10]);
+    let ptr = buffer.get_raw() as *const libc::c_void;
+    let id3 = trace::live_id(ptr).unwrap();
+    assert!(id3 > id2);
+    buffer.set_len(10);
+    buffer.push(1).unwrap();
+    assert_ne!(trace::live_id(buffer.get_raw() as *const libc::c_void), Some(id3));
+    let ptr = buffer.get_raw() as *const libc::c_void;
+    // Released to the Vec, which holds the ptr
+    let v: Vec<u8> = buffer.into();
+    assert_eq!(trace::live_id(ptr), None);
+    drop(v);
+}
+
+#[test]
+fn test_as_capacity_mut() {
+    let mut buffer = Buffer::with_capacity(100).unwrap();
+    assert_eq!(buffer.len(), 0);
+    let space = unsafe { buffer.as_capacity_mut() };
+    assert_eq!(space.len(), 100);
+    for (i, b) in space.iter_mut().enumerate() {
+        *b = i as u8;
+    }
+    buffer.commit_len(100);
+    assert_eq!(buffer.len(), 100);
+    assert_eq!(buffer[99], 99);
+    // Partial frame
+    let space = unsafe { buffer.as_capacity_mut() };
+    space[0..4].copy_from_slice(b"abcd");
+    buffer.commit_len(4);
+    assert_eq!(&buffer[..], b"abcd");
+    assert_eq!(unsafe { buffer.as_capacity_mut() }.len(), 100);
+}
+
+#[test]
+#[should_panic]
+fn test_commit_len_over_capacity() {
+    let mut buffer = Buffer::with_capacity(100).unwrap();
+    buffer.commit_len(101);
+}
+
+#[test]
+fn test_try_reserve_exact() {
+    use nix::errno::Errno;
+    let mut buffer = Buffer::from(b"hello".to_vec());
+    buffer.try_reserve_exact(100).unwrap();
+    assert_eq!(buffer.capacity(), 105);
+    assert_eq!(&buffer[..], b"hello");
+    // Enough already
+    buffer.try_reserve_exact(50).unwrap();
+    assert_eq!(buffer.capacity(), 105);
+    let mut buffer = Buffer::aligned(512).unwrap();
+    buffer.try_reserve_exact(1).unwrap();
+    assert_eq!(buffer.capacity(), 1024);
+    assert!(buffer.is_aligned());
+    assert_eq!(buffer.try_reserve_exact(usize::MAX), Err(Errno::EOVERFLOW));
+    assert_eq!(buffer.try_reserve_exact(MAX_BUFFER_SIZE), Err(Errno::EOVERFLOW));
+    assert_eq!(buffer.capacity(), 1024);
+    let mut buffer = Buffer::aligned_by(4096, 4096).unwrap();
+    buffer.try_reserve_exact(1).unwrap();
+    assert_eq!(buffer.capacity(), 8192);
+    assert_eq!(buffer.get_raw() as usize % 4096, 0);
+    let mut data = [0u8; 10];
+    let mut buf_ref = unsafe { Buffer::from_slice_mut(&mut data) };
+    assert_eq!(buf_ref.try_reserve_exact(1), Err(Errno::EINVAL));
+    buf_ref.try_reserve_exact(0).unwrap();
+}
+
+#[test]
+fn test_clone_c_ref_deterministic() {
+    let mut buffer = Buffer::aligned_by(8192, 4096).unwrap();
+    buffer.zero();
+    let cloned = buffer.clone();
+    assert_eq!(cloned.alloc_align(), 4096);
+    assert_eq!(cloned.get_raw() as usize % 4096, 0);
+    // The c ref happens to be aligned
+    let buf_ref = Buffer::from_c_ref_mut(buffer.get_raw_mut() as *mut libc::c_void, 4096);
+    assert!(buf_ref.is_aligned());
+    let cloned = buf_ref.clone();
+    assert!(cloned.is_owned());
+    assert_eq!(cloned.alloc_align(), 0);
+    assert_eq!(&cloned[..], &buffer[0..4096]);
+    let buf_ref = unsafe { Buffer::from_slice_ref(&buffer[0..512]) };
+    assert_eq!(buf_ref.clone().alloc_align(), 0);
+}
+
+#[test]
+fn test_clone_owned_deterministic() {
+    // An owned malloc buffer happens to be aligned
+    let mut ptr: *mut libc::c_void = std::ptr::null_mut();
+    assert_eq!(unsafe { libc::posix_memalign(&mut ptr, 4096, 4096) }, 0);
+    let mut buffer = unsafe { Buffer::from_malloc_raw(ptr, 4096, 4096) };
+    buffer.zero();
+    assert!(buffer.is_aligned());
+    let cloned = buffer.clone();
+    assert_eq!(cloned.alloc_align(), 0);
+    assert_eq!(&cloned[..], &buffer[..]);
+    for chunk in buffer.into_owned_chunks(1000) {
+        assert_eq!(chunk.alloc_align(), 0);
+    }
+}
+
+#[test]
+fn test_into_owned_chunks() {
+    let content: Vec<u8> = (0..2500u32).map(|i| i as u8).collect();
+    let mut buffer = Buffer::aligned(4096).unwrap();
+    buffer.set_len(2500);
+    buffer.copy_from(0, &content);
+    let chunks = buffer.into_owned_chunks(1024);
+    assert_eq!(chunks.iter().map(|c| c.len()).collect::<Vec<_>>(), vec![1024, 1024, 452]);
+    for (i, c) in chunks.iter().enumerate() {
+        assert!(c.is_owned());
+        assert!(c.is_aligned());
+        assert_eq!(&c[..], &content[i * 1024..(i * 1024 + c.len())]);
+    }
+    // Move into workers
+    let ths: Vec<_> = chunks
+        .into_iter()
+        .map(|mut c| std::thread::spawn(move || c.iter_mut().for_each(|b| *b = 0)))
+        .collect();
+    for th in ths {
+        th.join().unwrap();
+    }
+    let chunks = Buffer::from(content.clone()).into_owned_chunks(1000);
+    assert_eq!(chunks.len(), 3);
+    assert_eq!(&chunks[2][..], &content[2000..]);
+    assert!(Buffer::from(Vec::new()).into_owned_chunks(10).is_empty());
+}
+
+#[test]
+fn test_split_at_mut() {
+    let mut buffer = Buffer::from(b"abcdef".to_vec());
+    let (head, tail) = buffer.split_at_mut(2);
+    assert_eq!(head, b"ab");
+    tail.copy_from_slice(b"WXYZ");
+    head[0] = tail[3];
+    assert_eq!(&buffer[..], b"ZbWXYZ");
+    let (head, tail) = buffer.split_at_mut(6);
+    assert_eq!((head.len(), tail.len()), (6, 0));
+}
+
+#[test]
+#[should_panic(expected = "out of buffer size")]
+fn test_split_at_mut_out_of_range() {
+    let mut buffer = Buffer::from(b"abcdef".to_vec());
+    buffer.set_len(3);
+    let _ = buffer.split_at_mut(4);
+}
+
+#[test]
+fn test_diff() {
+    let content: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+    let buffer = Buffer::from(content.clone());
+    assert_eq!(buffer.diff(&content), None);
+    buffer.assert_eq_bytes(&content);
+    let mut other = content.clone();
+    other[777] ^= 0x10;
+    assert_eq!(buffer.diff(&other), Some((777, content[777], other[777])));
+    // Equal up to the shorter length
+    assert_eq!(buffer.diff(&content[0..500]), None);
+    let r = std::panic::catch_unwind(|| buffer.assert_eq_bytes(&other));
+    let msg = r.unwrap_err().downcast::<String>().unwrap();
+    assert!(msg.contains("differ at offset 777"), "{}", msg);
+    assert!(msg.contains(" 19 ") && msg.contains(" 09 "), "{}", msg);
+    let r = std::panic::catch_unwind(|| buffer.assert_eq_bytes(&content[0..500]));
+    let msg = r.unwrap_err().downcast::<String>().unwrap();
+    assert!(msg.contains("differ at offset 500 (len 1000 vs 500)"), "{}", msg);
+}
+
+#[test]
+fn test_pad_to_sector() {
+    use nix::errno::Errno;
+    let mut buffer = Buffer::aligned(512).unwrap();
+    buffer.iter_mut().for_each(|b| *b = 1);
+    buffer.set_len(300);
+    buffer.pad_to_sector(4096).unwrap();
+    assert_eq!(buffer.len(), 300);
+    assert_eq!(buffer.capacity(), 4096);
+    assert!(buffer.is_aligned());
+    buffer.pad_len_to_sector(4096).unwrap();
+    assert_eq!(buffer.len(), 4096);
+    assert!(buffer[0..300].iter().all(|b| *b == 1));
+    assert!(buffer[300..].iter().all(|b| *b == 0));
+    // Already on sector boundary
+    buffer.pad_len_to_sector(512).unwrap();
+    assert_eq!(buffer.len(), 4096);
+    assert_eq!(buffer.alloc_align(), 4096);
+    assert_eq!(buffer.get_raw() as usize % 4096, 0);
+    let mut buffer = Buffer::aligned_by(4096, 4096).unwrap();
+    buffer.set_len(4000);
+    buffer.pad_to_sector(512).unwrap();
+    assert_eq!(buffer.capacity(), 4096);
+    buffer.set_len(4096);
+    buffer.pad_to_sector(4096).unwrap();
+    buffer.push(1).unwrap();
+    buffer.pad_len_to_sector(512).unwrap();
+    assert_eq!(buffer.len(), 4608);
+    assert_eq!(buffer.capacity(), 8192);
+    assert_eq!(buffer.get_raw() as usize % 4096, 0);
+    let mut buffer = Buffer::from(vec![1u8; 10]);
+    buffer.pad_len_to_sector(512).unwrap();
+    assert_eq!(buffer.len(), 512);
+    assert_eq!(buffer[10], 0);
+    assert_eq!(buffer.pad_to_sector(0), Err(Errno::EINVAL));
+    // c ref with enough capacity
+    let mut data = [1u8; 16];
+    let mut buf_ref = unsafe { Buffer::from_slice_mut(&mut data) };
+    buf_ref.set_len(5);
+    buf_ref.pad_len_to_sector(8).unwrap();
+    assert_eq!(&buf_ref[..], &[1, 1, 1, 1, 1, 0, 0, 0]);
+    assert_eq!(buf_ref.pad_to_sector(32), Err(Errno::EINVAL));
+    let mut buf_const = unsafe { Buffer::from_slice_ref(b"abc") };
+    assert_eq!(buf_const.pad_to_sector(4), Err(Errno::EINVAL));
+}
+
+#[test]
+fn test_aligned_by_invalid() {
+    use nix::errno::Errno;
+    // Not power of two
+    assert_eq!(Buffer::aligned_by(1536 * 2, 1536).unwrap_err(), Errno::EINVAL);
+    // Size not multiple of align
+    assert_eq!(Buffer::aligned_by(1000, 512).unwrap_err(), Errno::EINVAL);
+    assert_eq!(Buffer::aligned_by(4096 + 512, 4096).unwrap_err(), Errno::EINVAL);
+    assert_eq!(Buffer::aligned(100).unwrap_err(), Errno::EINVAL);
+    let buffer = Buffer::aligned_by(8192, 4096).unwrap();
+    assert_eq!(buffer.get_raw() as usize % 4096, 0);
+    // Alignment from runtime queries
+    assert_eq!(Buffer::aligned_by(4096, 0).unwrap_err(), Errno::EINVAL);
+    assert_eq!(Buffer::aligned_by(4096, 3).unwrap_err(), Errno::EINVAL);
+    // Power of two, but not multiple of 512
+    assert_eq!(Buffer::aligned_by(4096, 256).unwrap_err(), Errno::EINVAL);
+}
+
+#[test]
+fn test_into_arc_slice() {
+    let mut buffer = Buffer::aligned(4096).unwrap();
+    buffer.set_len(5);
+    buffer.copy_from(0, b"hello");
+    let arc = buffer.into_arc_slice();
+    assert_eq!(&arc[..], b"hello");
+    // Zero-copy view back
+    let view = Buffer::from_arc_slice(arc.clone());
+    assert_eq!(view.as_buffer().get_raw(), arc.as_ptr());
+    assert_eq!(&view[..], b"hello");
+    let arc = Buffer::from(Vec::new()).into_arc_slice();
+    assert!(arc.is_empty());
+}
+
+#[test]
+fn test_resize_and_zero() {
+    use nix::errno::Errno;
+    let mut buffer = Buffer::alloc(10).unwrap();
+    buffer.iter_mut().for_each(|b| *b = 0xff);
+    buffer.set_len(4);
+    // Grow within capacity, the stale bytes must be zeroed
+    buffer.resize_and_zero(8).unwrap();
+    assert_eq!(&buffer[..], &[0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0]);
+    // Grow beyond capacity
+    buffer.resize_and_zero(1000).unwrap();
+    assert_eq!(buffer.len(), 1000);
+    assert!(buffer[4..].iter().all(|b| *b == 0));
+    assert_eq!(&buffer[0..4], &[0xff; 4]);
+    // Same size
+    buffer.resize_and_zero(1000).unwrap();
+    assert_eq!(buffer.len(), 1000);
+    // Shrink
+    let cap = buffer.capacity();
+    buffer.resize_and_zero(2).unwrap();
+    assert_eq!(&buffer[..], &[0xff, 0xff]);
+    assert_eq!(buffer.capacity(), cap);
+    assert_eq!(buffer.resize_and_zero(-1), Err(Errno::EINVAL));
+    let mut data = [0u8; 10];
+    let mut buf_ref = unsafe { Buffer::from_slice_mut(&mut data) };
+    assert_eq!(buf_ref.resize_and_zero(5), Err(Errno::EINVAL));
+}
+
+#[test]
+fn test_read_into_spare() {
+    use std::io::Read;
+    let mut buffer = Buffer::with_capacity(10).unwrap();
+    let mut src: &[u8] = b"0123456789abcdef";
+    assert_eq!(buffer.read_into_spare(|spare| src.read(spare)).unwrap(), 10);
+    assert_eq!(&buffer[..], b"0123456789");
+    // Full, nothing read
+    assert_eq!(buffer.read_into_spare(|spare| src.read(spare)).unwrap(), 0);
+    buffer.set_len(4);
+    assert_eq!(buffer.read_into_spare(|spare| src.read(&mut spare[0..3])).unwrap(), 3);
+    assert_eq!(&buffer[..], b"0123abc");
+    let e = buffer.read_into_spare(|_| Ok(100)).unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    assert_eq!(buffer.len(), 7);
+    let e = buffer.read_into_spare(|_| Err(std::io::Error::other("broken"))).unwrap_err();
+    assert_eq!(e.to_string(), "broken");
+    assert_eq!(buffer.len(), 7);
+}
+
+#[test]
+fn test_subbuffer_owned() {
+    let data = *b"0123456789";
+    let source = unsafe { Buffer::from_slice_ref(&data) };
+    let mut sub = source.subbuffer_owned(2..6).unwrap();
+    assert!(sub.is_owned());
+    assert!(sub.is_mutable());
+    assert_eq!(sub.len(), 4);
+    assert_eq!(&sub[..], b"2345");
+    sub[0] = b'X';
+    assert_eq!(&source[..], b"0123456789");
+    let sub = std::thread::spawn(move || sub.len()).join().unwrap();
+    assert_eq!(sub, 4);
+    assert_eq!(source.subbuffer_owned(10..10).unwrap().len(), 0);
+    assert_eq!(&source.subbuffer_owned(0..10).unwrap()[..], &data[..]);
+}
+
+#[test]
+#[should_panic(expected = "out of buffer size")]
+fn test_subbuffer_owned_out_of_range() {
+    let source = unsafe { Buffer::from_slice_ref(b"0123456789") };
+    let _ = source.subbuffer_owned(5..11);
+}
+
+#[test]
+fn test_checksum_chunked() {
+    let content: Vec<u8> = (0..10000u32).map(|i| (i % 253) as u8).collect();
+    let buffer = Buffer::from(content.clone());
+    for chunk in [1, 100, 4096, 10000, 20000] {
+        let mut copied = Vec::new();
+        let checksum =
+            buffer.checksum_chunked::<Crc32cHasher>(chunk, |data| copied.extend_from_slice(data));
+        assert_eq!(checksum, crc32c(&content) as u64);
+        assert_eq!(copied, content);
+    }
+    let mut calls = 0;
+    let checksum = Buffer::from(Vec::new()).checksum_chunked::<Crc32cHasher>(10, |_| calls += 1);
+    assert_eq!((checksum, calls), (0, 0));
+}
+
+#[test]
+fn test_locate_corruption() {
+    let content: Vec<u8> = (0..10000u32).map(|i| (i % 253) as u8).collect();
+    let expected: Vec<u64> = content.chunks(4096).map(|c| crc32c(c) as u64).collect();
+    let mut buffer = Buffer::from(content);
+    assert!(buffer.locate_corruption::<Crc32cHasher>(4096, &expected).is_empty());
+    buffer[5000] ^= 1;
+    assert_eq!(buffer.locate_corruption::<Crc32cHasher>(4096, &expected), vec![1]);
+    buffer[9999] ^= 1;
+    assert_eq!(buffer.locate_corruption::<Crc32cHasher>(4096, &expected), vec![1, 2]);
+    // Missing checksums
+    assert_eq!(buffer.locate_corruption::<Crc32cHasher>(4096, &expected[0..1]), vec![1, 2]);
+}
+
+#[test]
+fn test_buffer_registry() {
+    use nix::errno::Errno;
+    let mut registry = BufferRegistry::new();
+    let mut ptrs = Vec::new();
+    for size in [4096, 8192, 512] {
+        let mut buf = Buffer::aligned(size).unwrap();
+        buf.set_len(100);
+        ptrs.push((buf.get_raw() as usize, size as usize));
+        let idx = registry.register(buf).unwrap();
+        assert_eq!(idx as usize, ptrs.len() - 1);
+    }
+    assert_eq!(registry.len(), 3);
+    for (iov, (ptr, size)) in registry.iovecs().iter().zip(ptrs.iter()) {
+        assert_eq!(iov.iov_base as usize, *ptr);
+        assert_eq!(iov.iov_len, *size);
+    }
+    registry.get_mut(1).unwrap()[0] = 1;
+    let (iov, idx) = registry.get(1).unwrap().as_iovec_registered(1);
+    assert_eq!((iov.iov_base as usize, iov.iov_len, idx), (ptrs[1].0, 8192, 1));
+    assert_eq!(registry.register(Buffer::from(Vec::new())), Err(Errno::EINVAL));
+    assert_eq!(registry.register(unsafe { Buffer::from_slice_ref(b"const") }), Err(Errno::EINVAL));
+    let bufs = registry.into_buffers();
+    assert_eq!(bufs[1][0], 1);
+    assert_eq!(bufs[2].get_raw() as usize, ptrs[2].0);
+}
+
+#[test]
+fn test_try_into_boxed_slice() {
+    let buffer = Buffer::from(b"boxed".to_vec().into_boxed_slice());
+    let ptr = buffer.get_raw();
+    let boxed = buffer.try_into_boxed_slice().unwrap();
+    assert_eq!(&boxed[..], b"boxed");
+    assert_eq!(boxed.as_ptr(), ptr);
+    // len != capacity
+    let mut buffer = Buffer::alloc(10).unwrap();
+    buffer.set_len(5);
+    let buffer = buffer.try_into_boxed_slice().unwrap_err();
+    assert_eq!(buffer.len(), 5);
+    let buffer = unsafe { Buffer::from_slice_ref(b"ref") };
+    assert!(buffer.try_into_boxed_slice().is_err());
+    let boxed = Buffer::from(Vec::new()).try_into_boxed_slice().unwrap();
+    assert!(boxed.is_empty());
+}
+
+#[test]
+fn test_nt() {
+    let src: Vec<u8> = (0..100000u32).map(|i| (i % 251) as u8 + 1).collect();
+    let mut buffer = Buffer::alloc(100003).unwrap();
+    let mut expected = Buffer::alloc(100003).unwrap();
+    buffer.copy_from_nt(3, &src);
+    expected.copy_from(3, &src);
+    assert_eq!(&buffer[3..], &expected[3..]);
+    buffer.copy_from_nt(0, &src[0..10]);
+    assert_eq!(&buffer[0..10], &src[0..10]);
+    buffer.set_zero_nt();
+    assert!(is_all_zero(&buffer));
+}
+
+#[test]
+fn test_benchmark_nt() {
+    let size = 64 * 1024 * 1024;
+    let src = vec![1u8; size];
+    let mut buffer = Buffer::aligned(size as i32).unwrap();
+    let round = 10;
+    let bench = |name: &str, f: &mut dyn FnMut()| {
+        let start_ts = Instant::now();
+        for _ in 0..round {
+            f();
+        }
+        let secs = Instant::now().duration_since(start_ts).as_secs_f64();
+        println!("{} speed {:.0} MB/sec", name, (size * round) as f64 / secs / 1024.0 / 1024.0);
+    };
+    bench("zero", &mut || buffer.zero());
+    bench("set_zero_nt", &mut || buffer.set_zero_nt());
+    bench("copy_from", &mut || buffer.copy_from(0, &src));
+    bench("copy_from_nt", &mut || buffer.copy_from_nt(0, &src));
+}
+
+#[test]
+fn test_index() {
+    let mut buffer = Buffer::from(b"0123456789".to_vec());
+    assert_eq!(buffer[3], b'3');
+    assert_eq!(&buffer[2..4], b"23");
+    assert_eq!(&buffer[8..], b"89");
+    assert_eq!(&buffer[..2], b"01");
+    assert_eq!(&buffer[..], b"0123456789");
+    assert_eq!(&buffer[1..=2], b"12");
+    assert_eq!(&buffer[..=1], b"01");
+    buffer[0] = b'x';
+    buffer[1..3].copy_from_slice(b"yz");
+    assert_eq!(&buffer[0..4], b"xyz3");
+    buffer.set_len(5);
+    let msg = |r: std::thread::Result<()>| *r.unwrap_err().downcast::<String>().unwrap();
+    let r = std::panic::catch_unwind(|| {
+        let _ = buffer[5];
+    });
+    assert_eq!(msg(r), "index 5 out of bounds for buffer of length 5");
+    let r = std::panic::catch_unwind(|| {
+        let _ = &buffer[3..7];
+    });
+    assert_eq!(msg(r), "range 3..7 out of bounds for buffer of length 5");
+    let r = std::panic::catch_unwind(|| {
+        let _ = &buffer[..=5];
+    });
+    assert_eq!(msg(r), "range ..=5 out of bounds for buffer of length 5");
+}
+
+#[test]
+fn test_leak() {
+    let mut buffer = Buffer::alloc(100).unwrap();
+    buffer.set_len(10);
+    let ptr = buffer.get_raw();
+    let leaked: &'static mut [u8] = buffer.leak();
+    assert_eq!(leaked.len(), 10);
+    assert_eq!(leaked.as_ptr(), ptr);
+    leaked.fill(7);
+    assert_eq!(leaked[9], 7);
+    #[cfg(feature = "trace-alloc")]
+    assert!(trace::live_id(ptr as *const libc::c_void).is_some());
+}
+
+#[test]
+#[should_panic(expected = "not owned")]
+fn test_leak_c_ref() {
+    let mut data = [0u8; 10];
+    let _ = unsafe { Buffer::from_slice_mut(&mut data) }.leak();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_into_memfd() {
+    use std::os::fd::AsRawFd;
+    let content: Vec<u8> = (0..10000u32).map(|i| (i % 241) as u8).collect();
+    let (fd, mut map) = Buffer::from(content.clone()).into_memfd("io_buffer_test").unwrap();
+    assert_eq!(&map[..], &content[..]);
+    // Another mapping, as the receiver does, sees the writes
+    map[0] = 0xff;
+    let file = std::fs::File::from(fd);
+    assert_eq!(file.metadata().unwrap().len(), 10000);
+    let mut buf = Buffer::alloc(10).unwrap();
+    let n = unsafe { libc::pread(file.as_raw_fd(), buf.get_raw_mut() as *mut libc::c_void, 10, 0) };
+    assert_eq!(n, 10);
+    assert_eq!(buf[0], 0xff);
+    assert_eq!(&buf[1..], &content[1..10]);
+    drop(map);
+    assert_eq!(
+        Buffer::from(Vec::new()).into_memfd("empty").unwrap_err(),
+        nix::errno::Errno::EINVAL
+    );
+}
+
+#[test]
+fn test_aio_fields() {
+    let mut buf = Buffer::aligned(4096).unwrap();
+    buf.set_len(1024);
+    let (addr, nbytes) = buf.as_iocb_fields();
+    assert_eq!(addr, buf.get_raw() as u64);
+    assert_eq!(nbytes, 1024);
+    let bufs = [Buffer::aligned(512).unwrap(), Buffer::aligned(4096).unwrap()];
+    let iovecs = Buffer::aio_iovecs(&bufs);
+    assert_eq!(iovecs.len(), 2);
+    assert_eq!(iovecs[1].iov_base as *const u8, bufs[1].get_raw());
+    assert_eq!(iovecs[1].iov_len, 4096);
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic]
+fn test_aio_fields_unaligned() {
+    let buf = Buffer::from(vec![0u8; 100]);
+    let _ = Buffer::aio_iovecs(&[buf]);
+}
+
+#[test]
+fn test_shrink_to() {
+    let mut buffer = Buffer::alloc(4000).unwrap();
+    for i in 0..4000 {
+        buffer[i] = i as u8;
+    }
+    buffer.set_len(1000);
+    buffer.shrink_to(5000).unwrap();
+    assert_eq!(buffer.capacity(), 4000);
+    buffer.shrink_to(2000).unwrap();
+    assert_eq!(buffer.capacity(), 2000);
+    assert_eq!(buffer.len(), 1000);
+    // Not below len
+    buffer.shrink_to(10).unwrap();
+    assert_eq!(buffer.capacity(), 1000);
+    for i in 0..1000 {
+        assert_eq!(buffer[i], i as u8);
+    }
+
+    let mut buffer = Buffer::aligned(65536).unwrap();
+    buffer.set_len(100);
+    buffer[99] = 1;
+    buffer.shrink_to(4000).unwrap();
+    assert!(buffer.is_aligned());
+    assert_eq!(buffer.capacity(), 4096);
+    assert_eq!(buffer.len(), 100);
+    assert_eq!(buffer[99], 1);
+    let ptr = buffer.get_raw();
+    buffer.shrink_to(3585).unwrap();
+    assert_eq!(buffer.get_raw(), ptr);
+
+    let mut buffer = Buffer::aligned_by(65536, 4096).unwrap();
+    buffer.set_len(100);
+    buffer.shrink_to(1000).unwrap();
+    assert_eq!(buffer.capacity(), 4096);
+    assert_eq!(buffer.get_raw() as usize % 4096, 0);
+    let ptr = buffer.get_raw();
+    buffer.shrink_to(512).unwrap();
+    assert_eq!(buffer.get_raw(), ptr);
+
+    let mut data: [u8; 64] = [0; 64];
+    let mut buffer = unsafe { Buffer::from_slice_mut(&mut data) };
+    assert_eq!(buffer.shrink_to(10).unwrap_err(), nix::errno::Errno::EINVAL);
+}
+
+#[test]
+fn test_verify_equal() {
+    let mut a = Buffer::aligned(4096).unwrap();
+    for i in 0..4096 {
+        a[i] = (i % 251) as u8;
+    }
+    let mut b = a.clone();
+    assert_eq!(a.verify_equal(&b), Ok(()));
+    for offset in [0, 63, 64, 1000, 4095] {
+        b[offset] ^= 0x10;
+        assert_eq!(a.verify_equal(&b), Err(offset));
+        assert_eq!(b.verify_equal(&a), Err(offset));
+        b[offset] ^= 0x10;
+    }
+    // Differing lengths
+    b.set_len(3000);
+    assert_eq!(a.verify_equal(&b), Err(3000));
+    assert_eq!(b.verify_equal(&a), Err(3000));
+    b[10] = 0;
+    assert_eq!(a.verify_equal(&b), Err(10));
+    let empty = Buffer::from(Vec::new());
+    assert_eq!(empty.verify_equal(&Buffer::from(Vec::new())), Ok(()));
+    assert_eq!(empty.verify_equal(&a), Err(0));
+}
+
+#[test]
+fn test_fill_from() {
+    use std::io::{Error, ErrorKind, Read};
+
+    /// Return short reads, with Interrupted in between
+    struct Choppy<'a> {
+        data: &'a [u8],
+        count: usize,
+    }
+
+    impl Read for Choppy<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.count += 1;
+            if self.count.is_multiple_of(2) {
+                return Err(Error::from(ErrorKind::Interrupted));
+            }
+            let n = buf.len().min(3).min(self.data.len());
+            buf[0..n].copy_from_slice(&self.data[0..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    struct Broken;
+
+    impl Read for Broken {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            Err(Error::other("broken"))
+        }
+    }
+
+    let mut buffer = Buffer::with_capacity(10).unwrap();
+    let mut reader = Choppy { data: b"0123456789abcdef", count: 0 };
+    assert_eq!(buffer.fill_from(&mut reader).unwrap(), 10);
+    assert_eq!(&buffer[..], b"0123456789");
+    // Full
+    assert_eq!(buffer.fill_from(&mut reader).unwrap(), 0);
+    // EOF
+    let mut buffer = Buffer::with_capacity(100).unwrap();
+    buffer.push(b'x').unwrap();
+    assert_eq!(buffer.fill_from(&mut reader).unwrap(), 6);
+    assert_eq!(&buffer[..], b"xabcdef");
+    // Error after partial read
+    let mut reader = Choppy { data: b"0123", count: 0 }.chain(Broken);
+    let mut buffer = Buffer::with_capacity(100).unwrap();
+    let e = buffer.fill_from(&mut reader).unwrap_err();
+    assert_eq!(e.to_string(), "broken");
+    assert_eq!(&buffer[..], b"0123");
+}
+
+#[test]
+fn test_parse_length_prefixed() {
+    let buffer = Buffer::from(b"\x00\x05hello world".to_vec());
+    let (len, payload) = buffer.parse_length_prefixed(2, true).unwrap();
+    assert_eq!(len, 5);
+    assert_eq!(&payload[..], b"hello");
+    assert!(!payload.as_buffer().is_owned());
+    // Borrowing a shared self, the view is immutable
+    assert!(!payload.as_buffer().is_mutable());
+    assert_eq!(payload.as_buffer().get_raw(), unsafe { buffer.get_raw().add(2) });
+    // Little endian 0x0500 is a partial frame
+    assert!(buffer.parse_length_prefixed(2, false).is_none());
+    let buffer = Buffer::from(b"\x03\x00\x00\x00abc".to_vec());
+    let (len, payload) = buffer.parse_length_prefixed(4, false).unwrap();
+    assert_eq!(len, 3);
+    assert_eq!(&payload[..], b"abc");
+    let (len, payload) = buffer.parse_length_prefixed(1, true).unwrap();
+    assert_eq!(len, 3);
+    assert_eq!(&payload[..], b"\x00\x00\x00");
+    // Insufficient prefix
+    assert!(Buffer::from(b"\x00\x00".to_vec()).parse_length_prefixed(4, true).is_none());
+    let buffer = Buffer::from(vec![0u8; 8]);
+    let (len, payload) = buffer.parse_length_prefixed(8, true).unwrap();
+    assert_eq!(len, 0);
+    assert_eq!(payload.len(), 0);
+    let buffer = Buffer::from(vec![0xffu8; 16]);
+    assert!(buffer.parse_length_prefixed(8, true).is_none());
+}
+
+#[test]
+fn test_usable_size() {
+    let buffer = Buffer::alloc(1001).unwrap();
+    assert!(buffer.usable_size() >= buffer.capacity());
+    let buffer = Buffer::from(Vec::with_capacity(100));
+    assert!(buffer.usable_size() >= 100);
+    let buffer = Buffer::aligned(4096).unwrap();
+    assert_eq!(buffer.usable_size(), 4096);
+    let data = [0u8; 64];
+    assert_eq!(unsafe { Buffer::from_slice_ref(&data) }.usable_size(), 64);
+    assert_eq!(Buffer::from(Vec::new()).usable_size(), 0);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_map_file_mut() {
+    let path = temp_path("map_file_mut");
+    std::fs::write(&path, b"0123456789").unwrap();
+    {
+        let mut map = unsafe { Buffer::map_file_mut(&path, 8192) }.unwrap();
+        assert_eq!(map.len(), 8192);
+        assert_eq!(&map[0..10], b"0123456789");
+        assert!(is_all_zero(&map[10..]));
+        map[0..5].copy_from_slice(b"hello");
+        map[8191] = b'!';
+        map.msync().unwrap();
+        let content = std::fs::read(&path).unwrap();
+        assert_eq!(content.len(), 8192);
+        assert_eq!(&content[0..10], b"hello56789");
+        assert_eq!(content[8191], b'!');
+    }
+    // Shrink
+    let map = unsafe { Buffer::map_file_mut(&path, 5) }.unwrap();
+    assert_eq!(&map[..], b"hello");
+    drop(map);
+    assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+    assert_eq!(unsafe { Buffer::map_file_mut(&path, 0) }.unwrap_err(), nix::errno::Errno::EINVAL);
+    std::fs::remove_file(&path).unwrap();
+    let e = unsafe { Buffer::map_file_mut(std::path::Path::new("/nonexistent/dir/file"), 10) }
+        .unwrap_err();
+    assert_eq!(e, nix::errno::Errno::ENOENT);
+}
+
+#[test]
+fn test_from_reader_exact() {
+    let mut src: &[u8] = b"0123456789";
+    let buffer = Buffer::from_reader_exact(&mut src, 4).unwrap();
+    assert_eq!(&buffer[..], b"0123");
+    assert!(buffer.is_owned());
+    let buffer = Buffer::from_reader_exact(&mut src, 6).unwrap();
+    assert_eq!(&buffer[..], b"456789");
+    // Empty
+    let buffer = Buffer::from_reader_exact(&mut src, 0).unwrap();
+    assert_eq!(buffer.len(), 0);
+    // Short read
+    let mut src: &[u8] = b"0123";
+    let e = Buffer::from_reader_exact(&mut src, 5).unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn test_clone_aligned() {
+    let mut buffer = Buffer::aligned_by(8192, 4096).unwrap();
+    for i in 0..8192 {
+        buffer[i] = i as u8;
+    }
+    buffer.set_len(5000);
+    let cloned = buffer.clone_aligned(4096).unwrap();
+    assert_eq!(cloned.get_raw() as usize % 4096, 0);
+    assert!(cloned.is_aligned());
+    assert_eq!(cloned.len(), 5000);
+    assert_eq!(cloned.capacity(), 8192);
+    assert_eq!(&cloned[..], &buffer[..]);
+    // Unaligned source
+    let data = [7u8; 100];
+    let cloned = unsafe { Buffer::from_slice_ref(&data) }.clone_aligned(4096).unwrap();
+    assert_eq!(cloned.get_raw() as usize % 4096, 0);
+    assert!(cloned.is_owned());
+    assert_eq!(cloned.capacity(), 4096);
+    assert_eq!(&cloned[..], &data[..]);
+    let cloned = Buffer::from(Vec::new()).clone_aligned(512).unwrap();
+    assert_eq!((cloned.len(), cloned.capacity()), (0, 512));
+    assert_eq!(buffer.clone_aligned(1000).unwrap_err(), nix::errno::Errno::EINVAL);
+    assert_eq!(buffer.clone_aligned(256).unwrap_err(), nix::errno::Errno::EINVAL);
+}
+
+#[test]
+fn test_prepend() {
+    let mut buffer = Buffer::with_capacity(8).unwrap();
+    for b in b"payload" {
+        buffer.push(*b).unwrap();
+    }
+    buffer.prepend(b"\x00\x07").unwrap();
+    assert_eq!(&buffer[..], b"\x00\x07payload");
+    assert!(buffer.capacity() >= 9);
+    buffer.prepend(b"").unwrap();
+    assert_eq!(&buffer[..], b"\x00\x07payload");
+
+    let mut buffer = Buffer::from(Vec::new());
+    buffer.prepend(b"hdr").unwrap();
+    assert_eq!(&buffer[..], b"hdr");
+
+    let mut data = *b"abc_____";
+    let mut buffer = unsafe { Buffer::from_slice_mut(&mut data) };
+    buffer.set_len(3);
+    buffer.prepend(b"xy").unwrap();
+    assert_eq!(&buffer[..], b"xyabc");
+    assert_eq!(buffer.prepend(b"0123").unwrap_err(), nix::errno::Errno::EINVAL);
+    let mut buffer = unsafe { Buffer::from_slice_ref(b"abc") };
+    assert_eq!(buffer.prepend(b"x").unwrap_err(), nix::errno::Errno::EINVAL);
+}
+
+#[test]
+fn test_dma_safe() {
+    let mut buffer = Buffer::aligned_by(8192, 4096).unwrap();
+    assert!(buffer.is_dma_safe(512));
+    assert!(buffer.is_dma_safe(4096));
+    buffer.assert_dma_safe(4096);
+    buffer.set_len(1024);
+    assert!(buffer.is_dma_safe(512));
+    assert!(!buffer.is_dma_safe(4096));
+    buffer.set_len(0);
+    assert!(!buffer.is_dma_safe(512));
+    assert!(!buffer.is_dma_safe(1000));
+    let buffer = Buffer::alloc(1000).unwrap();
+    assert!(!buffer.is_dma_safe(512));
+}
+
+#[test]
+#[should_panic(expected = "buffer not dma safe: len 1024 is not multiple of 4096")]
+fn test_assert_dma_safe() {
+    let mut buffer = Buffer::aligned_by(8192, 4096).unwrap();
+    buffer.set_len(1024);
+    buffer.assert_dma_safe(4096);
+}
+
+#[test]
+fn test_split_header() {
+    let mut buffer = Buffer::from(b"HDR1body".to_vec());
+    let (header, body) = buffer.split_header::<4>().unwrap();
+    assert_eq!(header, b"HDR1");
+    assert_eq!(body, b"body");
+    let (header, body) = buffer.split_header::<8>().unwrap();
+    assert_eq!(header, b"HDR1body");
+    assert!(body.is_empty());
+    assert!(buffer.split_header::<9>().is_none());
+    let (header, body) = buffer.split_header_mut::<4>().unwrap();
+    header[3] = b'2';
+    body[0] = b'B';
+    assert_eq!(&buffer[..], b"HDR2Body");
+    assert!(buffer.split_header_mut::<9>().is_none());
+}
+
+#[test]
+fn test_interleave() {
+    let r = Buffer::from(b"rrRR".to_vec());
+    let g = Buffer::from(b"ggGG".to_vec());
+    let b = Buffer::from(b"bbBB".to_vec());
+    let mut out = Buffer::alloc(20).unwrap();
+    Buffer::interleave(&[&r, &g, &b], &mut out, 1).unwrap();
+    assert_eq!(&out[..], b"rgbrgbRGBRGB");
+    Buffer::interleave(&[&r, &g, &b], &mut out, 2).unwrap();
+    assert_eq!(&out[..], b"rrggbbRRGGBB");
+    let mut planes =
+        [Buffer::alloc(10).unwrap(), Buffer::alloc(10).unwrap(), Buffer::alloc(4).unwrap()];
+    let mut refs: Vec<&mut Buffer> = planes.iter_mut().collect();
+    Buffer::deinterleave(&out, &mut refs, 2).unwrap();
+    assert_eq!(&planes[0][..], b"rrRR");
+    assert_eq!(&planes[1][..], b"ggGG");
+    assert_eq!(&planes[2][..], b"bbBB");
+
+    // Invalid
+    let short = Buffer::from(b"ggG".to_vec());
+    let e = nix::errno::Errno::EINVAL;
+    assert_eq!(Buffer::interleave(&[&r, &short], &mut out, 1).unwrap_err(), e);
+    assert_eq!(Buffer::interleave(&[&r, &g], &mut out, 3).unwrap_err(), e);
+    assert_eq!(Buffer::interleave(&[&r, &g], &mut out, 0).unwrap_err(), e);
+    assert_eq!(Buffer::interleave(&[], &mut out, 1).unwrap_err(), e);
+    let mut small = Buffer::alloc(7).unwrap();
+    assert_eq!(Buffer::interleave(&[&r, &g], &mut small, 1).unwrap_err(), e);
+    let mut planes = [Buffer::alloc(10).unwrap(), Buffer::alloc(5).unwrap()];
+    let mut refs: Vec<&mut Buffer> = planes.iter_mut().collect();
+    assert_eq!(Buffer::deinterleave(&out, &mut refs, 1).unwrap_err(), e);
+    let mut refs: Vec<&mut Buffer> = planes.iter_mut().collect();
+    assert_eq!(Buffer::deinterleave(&out, &mut refs, 4).unwrap_err(), e);
+}
+
+#[test]
+fn test_vec_capacity_round_trip() {
+    for cap in [1, 4095, 1 << 20] {
+        let mut v: Vec<u8> = Vec::with_capacity(cap);
+        v.extend_from_slice(&[1]);
+        let cap = v.capacity();
+        let buffer = Buffer::from(v);
+        assert_eq!(buffer.capacity(), cap);
+        assert_eq!(buffer.len(), 1);
+        let v: Vec<u8> = buffer.into();
+        assert_eq!(v.capacity(), cap);
+        assert_eq!(v, vec![1]);
+    }
+}
+
+// Reserving 2 GiB may abort the test binary under strict overcommit, run with --ignored
+#[test]
+#[ignore]
+fn test_vec_capacity_max() {
+    let mut v: Vec<u8> = Vec::with_capacity(MAX_BUFFER_SIZE - 1);
+    v.push(1);
+    let cap = v.capacity();
+    let buffer = Buffer::from(v);
+    assert_eq!(buffer.capacity(), cap);
+    let v: Vec<u8> = buffer.into();
+    assert_eq!(v.capacity(), cap);
+    assert_eq!(v, vec![1]);
+}
+
+#[test]
+#[ignore]
+#[should_panic(expected = "cap 2147483648 >= 2147483648 is not supported")]
+fn test_vec_capacity_overflow() {
+    // Only reserved, the pages are not touched
+    let v: Vec<u8> = Vec::with_capacity(MAX_BUFFER_SIZE);
+    let _ = Buffer::from(v);
+}
+
+#[test]
+fn test_retain() {
+    let content: Vec<u8> = (0..1000u32).map(|i| (i % 7) as u8).collect();
+    let preds: [fn(u8) -> bool; 5] = [|b| b != 0, |b| b % 2 == 0, |b| b < 5, |_| true, |_| false];
+    for pred in preds {
+        let mut expected = content.clone();
+        expected.retain(|b| pred(*b));
+        let mut buffer = Buffer::from(content.clone());
+        buffer.retain(pred);
+        assert_eq!(&buffer[..], &expected[..]);
+        assert_eq!(buffer.capacity(), content.len());
+    }
+    // Long runs, f sees each original byte once in order
+    let content: Vec<u8> = (0..1000u32).map(|i| (i / 50 % 3) as u8 + (i % 2) as u8).collect();
+    let mut seen = Vec::new();
+    let mut buffer = Buffer::from(content.clone());
+    buffer.retain(|b| {
+        seen.push(b);
+        b != 1
+    });
+    assert_eq!(seen, content);
+    let expected: Vec<u8> = content.iter().copied().filter(|b| *b != 1).collect();
+    assert_eq!(&buffer[..], &expected[..]);
+}
+
+#[test]
+#[should_panic(expected = "buffer is not mutable")]
+fn test_retain_immutable() {
+    let mut buffer = unsafe { Buffer::from_slice_ref(b"0123") };
+    buffer.retain(|b| b != b'0');
+}
+
+#[test]
+fn test_chunks_mut() {
+    let mut buffer = Buffer::alloc(10000).unwrap();
+    buffer.zero();
+    let mut count = 0;
+    for (i, page) in buffer.chunks_mut(4096).enumerate() {
+        page.fill(i as u8 + 1);
+        count += 1;
+    }
+    assert_eq!(count, 3);
+    assert_eq!(buffer[4095], 1);
+    assert_eq!(buffer[4096], 2);
+    assert_eq!(buffer[9999], 3);
+    buffer.set_len(0);
+    assert_eq!(buffer.chunks_mut(4096).count(), 0);
+}
+
+#[test]
+fn test_from_malloc_raw() {
+    let ptr = unsafe { libc::malloc(100) };
+    unsafe { libc::memset(ptr, 7, 100) };
+    let mut buffer = unsafe { Buffer::from_malloc_raw(ptr, 10, 100) };
+    assert!(buffer.is_owned());
+    assert!(buffer.is_mutable());
+    assert_eq!(buffer.len(), 10);
+    assert_eq!(buffer.capacity(), 100);
+    assert_eq!(&buffer[..], &[7u8; 10]);
+    // Owned, can grow
+    buffer.set_len(100);
+    buffer.push(1).unwrap();
+    assert_eq!(buffer.len(), 101);
+    drop(buffer);
+
+    let mut ptr: *mut libc::c_void = std::ptr::null_mut();
+    assert_eq!(unsafe { libc::posix_memalign(&mut ptr, 4096, 4096) }, 0);
+    let buffer = unsafe { Buffer::from_malloc_raw(ptr, 4096, 4096) };
+    assert!(buffer.is_aligned());
+}
+
+#[test]
+#[should_panic(expected = "size 101 out of capacity 100")]
+fn test_from_malloc_raw_size() {
+    let ptr = unsafe { libc::malloc(100) };
+    let _ = unsafe { Buffer::from_malloc_raw(ptr, 101, 100) };
+}
+
+#[test]
+fn test_from_vec_aligned() {
+    let content: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+    let buffer = Buffer::from_vec_aligned(content.clone(), 4096).unwrap();
+    assert!(buffer.is_aligned());
+    assert_eq!(buffer.get_raw() as usize % 4096, 0);
+    assert_eq!(buffer.len(), 1000);
+    assert_eq!(buffer.capacity(), 4096);
+    assert_eq!(&buffer[..], &content[..]);
+
+    // Reused without copying when already aligned
+    let aligned = Buffer::aligned_by(8192, 4096).unwrap();
+    let ptr = aligned.get_raw();
+    let vec: Vec<u8> = aligned.into();
+    let buffer = Buffer::from_vec_aligned(vec, 4096).unwrap();
+    assert_eq!(buffer.get_raw(), ptr);
+    assert_eq!(buffer.capacity(), 8192);
+
+    let buffer = Buffer::from_vec_aligned(Vec::new(), 512).unwrap();
+    assert_eq!((buffer.len(), buffer.capacity()), (0, 512));
+    assert!(buffer.is_aligned());
+    assert_eq!(Buffer::from_vec_aligned(content, 1000).unwrap_err(), nix::errno::Errno::EINVAL);
+}
+
+#[test]
+fn test_write_guard() {
+    let mut buffer = Buffer::with_capacity(10).unwrap();
+    buffer.push(b'a').unwrap();
+    {
+        let mut guard = buffer.write_guard();
+        assert_eq!(guard.len(), 9);
+        guard[0..3].copy_from_slice(b"bcd");
+        guard.advance(3);
+        assert_eq!(guard.remaining(), 6);
+        assert_eq!(guard.len(), 6);
+        guard[0] = b'e';
+        guard[1] = b'x';
+        guard.advance(1);
+        assert_eq!(guard.advanced(), 4);
+    }
+    assert_eq!(&buffer[..], b"abcde");
+    // Nothing advanced
+    drop(buffer.write_guard());
+    assert_eq!(buffer.len(), 5);
+}
+
+#[test]
+#[should_panic(expected = "advance 6 out of spare capacity 5")]
+fn test_write_guard_overflow() {
+    let mut buffer = Buffer::with_capacity(5).unwrap();
+    buffer.write_guard().advance(6);
+}
+
+#[test]
+fn test_dedup() {
+    let inputs: [&[u8]; 6] = [b"", b"a", b"aaab", b"abbbc", b"abccc", b"zzzzzzzz"];
+    for input in inputs {
+        let mut expected = input.to_vec();
+        expected.dedup();
+        let mut buffer = Buffer::from(input.to_vec());
+        buffer.dedup();
+        assert_eq!(&buffer[..], &expected[..]);
+    }
+    let mut buffer = Buffer::from(b"  a  b\0\0\0".to_vec());
+    buffer.dedup();
+    assert_eq!(&buffer[..], b" a b\0");
+}
+
+#[test]
+fn test_to_aligned() {
+    let content: Vec<u8> = (0..1000u32).map(|i| i as u8 | 1).collect();
+    let mut buffer = Buffer::from(content.clone());
+    buffer.set_len(900);
+    let aligned = buffer.to_aligned(4096).unwrap();
+    assert_eq!(aligned.get_raw() as usize % 4096, 0);
+    assert!(aligned.is_owned());
+    assert_eq!(aligned.len(), 900);
+    assert_eq!(aligned.capacity(), 4096);
+    assert_eq!(&aligned[..], &content[0..900]);
+    let mut aligned = aligned;
+    aligned.set_len(4096);
+    assert!(is_all_zero(&aligned[900..]));
+    // Capacity follows len, not the source capacity
+    let mut big = Buffer::aligned(65536).unwrap();
+    big.set_len(512);
+    assert_eq!(big.to_aligned(512).unwrap().capacity(), 512);
+    let empty = Buffer::from(Vec::new()).to_aligned(512).unwrap();
+    assert_eq!((empty.len(), empty.capacity()), (0, 512));
+    assert_eq!(buffer.to_aligned(100).unwrap_err(), nix::errno::Errno::EINVAL);
+}
+
+#[cfg(feature = "fail")]
+#[test]
+fn test_copy_fd_to_fd_failpoints() {
+    use std::io::{Read, Seek, Write};
+    use std::os::fd::AsRawFd;
+    let _scenario = fail::FailScenario::setup();
+    let src_path = temp_path("copy_fail_src");
+    let dst_path = temp_path("copy_fail_dst");
+    let mut content = Buffer::alloc(10000).unwrap();
+    fastrand::fill(&mut content);
+    std::fs::File::create(&src_path).unwrap().write_all(&content).unwrap();
+    let mut src = std::fs::File::open(&src_path).unwrap();
+    let mut dst = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&dst_path)
+        .unwrap();
+    let mut buf = Buffer::aligned(4096).unwrap();
+
+    // Short reads / writes and an EINTR storm are handled by the loops
+    fail::cfg("copy_fd_read_short", "return").unwrap();
+    fail::cfg("copy_fd_write_short", "return").unwrap();
+    fail::cfg("copy_fd_read_err", "5*return(4)").unwrap();
+    fail::cfg("copy_fd_write_err", "5*return(4)").unwrap();
+    let copied = copy_fd_to_fd(src.as_raw_fd(), dst.as_raw_fd(), 10000, &mut buf).unwrap();
+    assert_eq!(copied, 10000);
+    let mut copy = Vec::new();
+    dst.rewind().unwrap();
+    dst.read_to_end(&mut copy).unwrap();
+    assert_eq!(&copy[..], &content[..]);
+    fail::remove("copy_fd_read_short");
+    fail::remove("copy_fd_write_short");
+
+    // EIO mid-transfer
+    src.rewind().unwrap();
+    fail::cfg("copy_fd_read_err", "1*off->return").unwrap();
+    let e = copy_fd_to_fd(src.as_raw_fd(), dst.as_raw_fd(), 10000, &mut buf).unwrap_err();
+    assert_eq!(e, nix::errno::Errno::EIO);
+    assert_eq!(src.stream_position().unwrap(), 4096);
+    fail::cfg("copy_fd_read_err", "off").unwrap();
+    fail::cfg("copy_fd_write_err", "return(28)").unwrap();
+    let e = copy_fd_to_fd(src.as_raw_fd(), dst.as_raw_fd(), 10000, &mut buf).unwrap_err();
+    assert_eq!(e, nix::errno::Errno::ENOSPC);
+    std::fs::remove_file(&src_path).unwrap();
+    std::fs::remove_file(&dst_path).unwrap();
+}
+
+#[test]
+fn test_overlaps() {
+    let mut data = [0u8; 100];
+    let whole = unsafe { Buffer::from_slice_mut(&mut data) };
+    let head = unsafe { Buffer::from_slice_ref(&whole[0..50]) };
+    let tail = unsafe { Buffer::from_slice_ref(&whole[50..]) };
+    let middle = unsafe { Buffer::from_slice_ref(&whole[40..60]) };
+    assert!(whole.overlaps(&head));
+    assert!(head.overlaps(&whole));
+    assert!(!head.overlaps(&tail));
+    assert!(!tail.overlaps(&head));
+    assert!(middle.overlaps(&head) && middle.overlaps(&tail));
+    assert!(whole.overlaps(&whole));
+    let empty = unsafe { Buffer::from_slice_ref(&whole[50..50]) };
+    assert!(!empty.overlaps(&whole) && !whole.overlaps(&empty));
+    assert!(!whole.overlaps(&Buffer::alloc(100).unwrap()));
+}
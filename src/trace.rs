@@ -0,0 +1,45 @@
+//! Trace-level logging of allocations, enabled with feature `trace-alloc`.
+//!
+//! Each owned allocation is given a monotonically increasing id, logged on alloc and free,
+//! to match the pairs when hunting a leak.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        LazyLock, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// ptr -> id of the live allocations
+static LIVE: LazyLock<Mutex<HashMap<usize, u64>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Record an owned allocation (or a Vec adopted), return the id.
+pub(crate) fn on_alloc(ptr: *const libc::c_void, size: usize, align: u32) -> u64 {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    LIVE.lock().unwrap().insert(ptr as usize, id);
+    log::trace!("buffer alloc id={} ptr={:p} size={} align={}", id, ptr, size, align);
+    id
+}
+
+/// Record an owned allocation freed (or released to a Vec).
+pub(crate) fn on_free(ptr: *const libc::c_void, size: usize) {
+    let id = LIVE.lock().unwrap().remove(&(ptr as usize));
+    match id {
+        Some(id) => log::trace!("buffer free id={} ptr={:p} size={}", id, ptr, size),
+        None => log::trace!("buffer free id=? ptr={:p} size={}", ptr, size),
+    }
+}
+
+/// Record a c ref wrapped, which is not owned.
+pub(crate) fn on_wrap(ptr: *const libc::c_void, size: usize, mutable: bool) {
+    log::trace!("buffer wrap ptr={:p} size={} mutable={}", ptr, size, mutable);
+}
+
+/// Return the id of a live allocation
+#[cfg(test)]
+pub(crate) fn live_id(ptr: *const libc::c_void) -> Option<u64> {
+    LIVE.lock().unwrap().get(&(ptr as usize)).copied()
+}
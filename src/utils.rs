@@ -1,3 +1,8 @@
+#![allow(clippy::needless_return)]
+
+use crate::{Buffer, BufferRef};
+use nix::errno::Errno;
+
 /// Only copy the 0..min(dst, src) of src to dst, return the bytes copied.
 #[inline]
 pub fn safe_copy(dst: &mut [u8], src: &[u8]) -> usize {
@@ -23,14 +28,76 @@ pub fn set_zero(dst: &mut [u8]) {
     }
 }
 
+/// Set a buffer to zero with non-temporal stores (bypassing the cache) on x86_64,
+/// for a large buffer that will not be read soon. Falls back to [set_zero()] on other targets.
+#[inline]
+pub fn set_zero_nt(dst: &mut [u8]) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        stream_x86_64(dst, None);
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    set_zero(dst);
+}
+
+/// Copy `src` into `dst` with non-temporal stores (bypassing the cache) on x86_64,
+/// the same as [safe_copy()] otherwise. Return the bytes copied, which is min(dst.len(), src.len()).
+#[inline]
+pub fn copy_nt(dst: &mut [u8], src: &[u8]) -> usize {
+    let len = dst.len().min(src.len());
+    #[cfg(target_arch = "x86_64")]
+    {
+        stream_x86_64(&mut dst[0..len], Some(&src[0..len]));
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    dst[0..len].copy_from_slice(&src[0..len]);
+    len
+}
+
+/// Store `src` (or zero when None) into dst, with `_mm_stream_si128` over the 16B aligned middle part.
+#[cfg(target_arch = "x86_64")]
+fn stream_x86_64(dst: &mut [u8], src: Option<&[u8]>) {
+    use std::arch::x86_64::{
+        __m128i, _mm_loadu_si128, _mm_setzero_si128, _mm_sfence, _mm_stream_si128,
+    };
+    let len = dst.len();
+    let head = dst.as_ptr().align_offset(16).min(len);
+    let body_end = head + (len - head) / 16 * 16;
+    let scalar = |dst: &mut [u8], range: std::ops::Range<usize>| match src {
+        Some(src) => dst[range.clone()].copy_from_slice(&src[range]),
+        None => set_zero(&mut dst[range]),
+    };
+    scalar(dst, 0..head);
+    // SAFETY: SSE2 is in the x86_64 baseline, and dst[head..body_end] is 16B aligned
+    unsafe {
+        let d = dst.as_mut_ptr();
+        match src {
+            Some(src) => {
+                let s = src.as_ptr();
+                for i in (head..body_end).step_by(16) {
+                    let v = _mm_loadu_si128(s.add(i) as *const __m128i);
+                    _mm_stream_si128(d.add(i) as *mut __m128i, v);
+                }
+            }
+            None => {
+                let zero = _mm_setzero_si128();
+                for i in (head..body_end).step_by(16) {
+                    _mm_stream_si128(d.add(i) as *mut __m128i, zero);
+                }
+            }
+        }
+        _mm_sfence();
+    }
+    scalar(dst, body_end..len);
+}
+
 /// Produce ascii random string
 #[cfg(feature = "rand")]
 #[inline]
 pub fn rand_buffer<T: AsMut<[u8]>>(dst: &mut T) {
     let s: &mut [u8] = dst.as_mut();
-    let len = s.len();
-    for i in 0..len {
-        s[i] = fastrand::alphanumeric() as u8;
+    for c in s.iter_mut() {
+        *c = fastrand::alphanumeric() as u8;
     }
 }
 
@@ -45,7 +112,98 @@ pub fn is_all_zero(s: &[u8]) -> bool {
     true
 }
 
+/// Return the length of the buffer without the trailing zero bytes,
+/// 0 when the buffer is all set to zero.
+///
+/// Scan backward a word at a time.
+#[inline]
+pub fn non_zero_len(s: &[u8]) -> usize {
+    const WORD: usize = std::mem::size_of::<usize>();
+    let mut end = s.len();
+    while !end.is_multiple_of(WORD) {
+        if s[end - 1] != 0 {
+            return end;
+        }
+        end -= 1;
+    }
+    while end >= WORD {
+        let word = usize::from_ne_bytes(s[end - WORD..end].try_into().unwrap());
+        if word != 0 {
+            break;
+        }
+        end -= WORD;
+    }
+    while end > 0 && s[end - 1] == 0 {
+        end -= 1;
+    }
+    end
+}
+
+/// Frame `payload` with a 4-byte big-endian length prefix, into a new owned buffer.
+///
+/// Return `EOVERFLOW` if the framed size is too large.
+pub fn frame_with_length_prefix(payload: &[u8]) -> Result<Buffer, Errno> {
+    let len = payload.len();
+    if len > u32::MAX as usize || len + 4 >= crate::MAX_BUFFER_SIZE {
+        return Err(Errno::EOVERFLOW);
+    }
+    let mut buf = Buffer::alloc(len as i32 + 4)?;
+    buf[0..4].copy_from_slice(&(len as u32).to_be_bytes());
+    buf[4..].copy_from_slice(payload);
+    Ok(buf)
+}
+
+/// Return the payload of a frame produced by [frame_with_length_prefix()],
+/// the bytes after the payload are ignored.
+///
+/// Return `EINVAL` if `src` is truncated.
+pub fn read_length_prefixed(src: &[u8]) -> Result<&[u8], Errno> {
+    let Some(prefix) = src.get(0..4) else {
+        return Err(Errno::EINVAL);
+    };
+    let len = u32::from_be_bytes(prefix.try_into().unwrap()) as usize;
+    src.get(4..4 + len).ok_or(Errno::EINVAL)
+}
+
+/// Pack equal-length planes into a new owned buffer, element by element:
+/// `planes[0][0], planes[1][0], ..., planes[0][1], planes[1][1], ...`.
+/// See [Buffer::interleave()].
+///
+/// Return `EINVAL` if there's no plane, `element_size` is zero, or the planes are not of the
+/// same length in multiple of `element_size`, `EOVERFLOW` if the output is too large.
+pub fn interleave(planes: &[&[u8]], element_size: usize) -> Result<Buffer, Errno> {
+    let total = planes.iter().map(|p| p.len()).sum::<usize>();
+    if total >= crate::MAX_BUFFER_SIZE {
+        return Err(Errno::EOVERFLOW);
+    }
+    let planes: Vec<BufferRef> = planes.iter().map(|p| BufferRef::from_slice(p)).collect();
+    let planes: Vec<&Buffer> = planes.iter().map(|p| p.as_buffer()).collect();
+    let mut out = Buffer::alloc(total.max(1) as i32)?;
+    Buffer::interleave(&planes, &mut out, element_size)?;
+    Ok(out)
+}
+
+/// The reverse of [interleave()], split the packed `src` into `planes` new owned buffers.
+/// See [Buffer::deinterleave()].
+///
+/// Return `EINVAL` if `planes` or `element_size` is zero, or `src.len()` is not multiple of
+/// `element_size * planes`.
+pub fn deinterleave(src: &[u8], planes: usize, element_size: usize) -> Result<Vec<Buffer>, Errno> {
+    if planes == 0 || src.len() >= crate::MAX_BUFFER_SIZE {
+        return Err(Errno::EINVAL);
+    }
+    let plane_len = src.len() / planes;
+    let mut out = Vec::with_capacity(planes);
+    for _ in 0..planes {
+        out.push(Buffer::alloc(plane_len.max(1) as i32)?);
+    }
+    let mut refs: Vec<&mut Buffer> = out.iter_mut().collect();
+    Buffer::deinterleave(BufferRef::from_slice(src).as_buffer(), &mut refs, element_size)?;
+    Ok(out)
+}
+
 #[cfg(test)]
+#[allow(clippy::needless_borrows_for_generic_args)]
 mod tests {
 
     extern crate md5;
@@ -92,4 +250,90 @@ mod tests {
             assert_eq!(*i, 0)
         }
     }
+
+    #[test]
+    fn test_nt() {
+        let src: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8 + 1).collect();
+        // Unaligned offsets and lengths
+        for (start, len) in [(0, 1000), (1, 999), (3, 17), (7, 0), (15, 100)] {
+            let mut buf = vec![0u8; 1100];
+            let copied = copy_nt(&mut buf[start..start + len], &src[0..]);
+            assert_eq!(copied, len);
+            assert_eq!(&buf[start..start + len], &src[0..len]);
+            assert!(is_all_zero(&buf[0..start]) && is_all_zero(&buf[start + len..]));
+            set_zero_nt(&mut buf[start..start + len]);
+            assert!(is_all_zero(&buf));
+        }
+        let mut buf = [0u8; 10];
+        assert_eq!(copy_nt(&mut buf, &src[0..5]), 5);
+        assert_eq!(&buf[0..5], &src[0..5]);
+    }
+
+    #[test]
+    fn test_non_zero_len() {
+        let mut buf: [u8; 37] = [0; 37];
+        assert_eq!(non_zero_len(&buf), 0);
+        assert_eq!(non_zero_len(&buf[0..0]), 0);
+        for i in 0..37 {
+            buf[i] = 1;
+            assert_eq!(non_zero_len(&buf), i + 1);
+            assert_eq!(non_zero_len(&buf[0..i]), 0);
+            buf[i] = 0;
+        }
+        buf[3] = 1;
+        buf[20] = 1;
+        assert_eq!(non_zero_len(&buf), 21);
+    }
+
+    #[test]
+    fn test_length_prefix() {
+        for payload in [&b""[..], b"x", b"hello world"] {
+            let framed = frame_with_length_prefix(payload).unwrap();
+            assert_eq!(framed.len(), payload.len() + 4);
+            assert_eq!(&framed[0..4], &(payload.len() as u32).to_be_bytes());
+            assert_eq!(read_length_prefixed(&framed).unwrap(), payload);
+        }
+        let framed = frame_with_length_prefix(b"hello").unwrap();
+        assert_eq!(&framed[..], b"\x00\x00\x00\x05hello");
+        // Trailing bytes ignored
+        let mut stream = framed.to_vec();
+        stream.extend_from_slice(b"next");
+        assert_eq!(read_length_prefixed(&stream).unwrap(), b"hello");
+        // Truncated
+        assert_eq!(read_length_prefixed(&framed[0..8]).unwrap_err(), Errno::EINVAL);
+        assert_eq!(read_length_prefixed(&framed[0..3]).unwrap_err(), Errno::EINVAL);
+        assert_eq!(read_length_prefixed(&[]).unwrap_err(), Errno::EINVAL);
+    }
+
+    #[test]
+    fn test_interleave() {
+        for element_size in [1, 4] {
+            for n in [2, 3] {
+                let planes: Vec<Vec<u8>> = (0..n)
+                    .map(|p| (0..element_size * 10).map(|i| (p * 100 + i) as u8).collect())
+                    .collect();
+                let refs: Vec<&[u8]> = planes.iter().map(|p| p.as_slice()).collect();
+                let packed = interleave(&refs, element_size).unwrap();
+                assert_eq!(packed.len(), n * element_size * 10);
+                // The second element of the last plane
+                let offset = (n + n - 1) * element_size;
+                assert_eq!(
+                    &packed[offset..offset + element_size],
+                    &planes[n - 1][element_size..element_size * 2]
+                );
+                let restored = deinterleave(&packed, n, element_size).unwrap();
+                assert_eq!(restored.len(), n);
+                for (plane, expected) in restored.iter().zip(&planes) {
+                    assert_eq!(&plane[..], &expected[..]);
+                }
+            }
+        }
+        assert_eq!(interleave(&[b"", b""], 1).unwrap().len(), 0);
+        assert_eq!(interleave(&[b"ab", b"c"], 1).unwrap_err(), Errno::EINVAL);
+        assert_eq!(interleave(&[b"abc", b"def"], 2).unwrap_err(), Errno::EINVAL);
+        assert_eq!(interleave(&[], 1).unwrap_err(), Errno::EINVAL);
+        assert_eq!(deinterleave(b"abcde", 2, 1).unwrap_err(), Errno::EINVAL);
+        assert_eq!(deinterleave(b"abcd", 0, 1).unwrap_err(), Errno::EINVAL);
+        assert_eq!(deinterleave(b"abcd", 2, 4).unwrap_err(), Errno::EINVAL);
+    }
 }
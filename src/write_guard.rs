@@ -0,0 +1,94 @@
+use crate::Buffer;
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+    slice,
+};
+
+/// Guard over the spare capacity of a [Buffer], returned by [Buffer::write_guard()].
+///
+/// Derefs to the spare bytes not yet reported, the count written is reported by
+/// [advance()](WriteGuard::advance()), and `len()` of the buffer is advanced by the total on drop,
+/// so the data written can not be left invisible by a forgotten `set_len()`.
+///
+/// ```
+/// use io_buffer::Buffer;
+/// let mut buf = Buffer::with_capacity(16).unwrap();
+/// {
+///     let mut guard = buf.write_guard();
+///     guard[0..5].copy_from_slice(b"hello");
+///     guard.advance(5);
+///     guard[0] = b'!';
+///     guard.advance(1);
+/// }
+/// assert_eq!(&buf[..], b"hello!");
+/// ```
+pub struct WriteGuard<'a> {
+    buf: &'a mut Buffer,
+    advanced: usize,
+}
+
+impl fmt::Debug for WriteGuard<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "WriteGuard({:?}, advanced={})", self.buf, self.advanced)
+    }
+}
+
+impl<'a> WriteGuard<'a> {
+    #[inline]
+    pub(crate) fn new(buf: &'a mut Buffer) -> Self {
+        assert!(buf.is_mutable(), "buffer is not mutable");
+        Self { buf, advanced: 0 }
+    }
+
+    /// Report `n` more bytes written at the front of the spare slice.
+    ///
+    /// # Panic
+    ///
+    /// If n is larger than the spare bytes remaining
+    #[inline]
+    pub fn advance(&mut self, n: usize) {
+        let remaining = self.remaining();
+        assert!(n <= remaining, "advance {} out of spare capacity {}", n, remaining);
+        self.advanced += n;
+    }
+
+    /// Return the total count reported by [advance()](WriteGuard::advance())
+    #[inline(always)]
+    pub fn advanced(&self) -> usize {
+        self.advanced
+    }
+
+    /// Return the count of spare bytes not yet reported
+    #[inline(always)]
+    pub fn remaining(&self) -> usize {
+        self.buf.margin() - self.advanced
+    }
+}
+
+impl Deref for WriteGuard<'_> {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        let offset = self.buf.len() + self.advanced;
+        unsafe { slice::from_raw_parts(self.buf.get_raw().add(offset), self.remaining()) }
+    }
+}
+
+impl DerefMut for WriteGuard<'_> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [u8] {
+        let offset = self.buf.len() + self.advanced;
+        let remaining = self.remaining();
+        unsafe { slice::from_raw_parts_mut(self.buf.get_raw_mut().add(offset), remaining) }
+    }
+}
+
+/// Commit the count advanced to `len()` on drop
+impl Drop for WriteGuard<'_> {
+    fn drop(&mut self) {
+        let len = self.buf.len() + self.advanced;
+        self.buf.set_len(len);
+    }
+}
@@ -0,0 +1,108 @@
+//! xxHash64, a fast non-cryptographic hash for dedup keys, exported with feature `xxhash`.
+//! Always built, for [Buffer::content_hash()](crate::Buffer::content_hash()).
+//!
+//! Unlike [crc32c()](crate::crc32c()) which is for integrity, this is for hashing and is much
+//! faster on large buffers.
+
+const PRIME64_1: u64 = 0x9E3779B185EBCA87;
+const PRIME64_2: u64 = 0xC2B2AE3D27D4EB4F;
+const PRIME64_3: u64 = 0x165667B19E3779F9;
+const PRIME64_4: u64 = 0x85EBCA77C2B2AE63;
+const PRIME64_5: u64 = 0x27D4EB2F165667C5;
+
+#[inline(always)]
+fn read_u64(s: &[u8]) -> u64 {
+    u64::from_le_bytes(s[0..8].try_into().unwrap())
+}
+
+#[inline(always)]
+fn read_u32(s: &[u8]) -> u32 {
+    u32::from_le_bytes(s[0..4].try_into().unwrap())
+}
+
+#[inline(always)]
+fn round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(PRIME64_2)).rotate_left(31).wrapping_mul(PRIME64_1)
+}
+
+#[inline(always)]
+fn merge_round(acc: u64, val: u64) -> u64 {
+    (acc ^ round(0, val)).wrapping_mul(PRIME64_1).wrapping_add(PRIME64_4)
+}
+
+/// Return the xxHash64 of data with seed 0, the same as the reference `XXH64(data, len, 0)`.
+pub fn xxhash64(data: &[u8]) -> u64 {
+    let len = data.len();
+    let mut s = data;
+    let mut h: u64;
+    if len >= 32 {
+        let mut v1 = PRIME64_1.wrapping_add(PRIME64_2);
+        let mut v2 = PRIME64_2;
+        let mut v3 = 0u64;
+        let mut v4 = 0u64.wrapping_sub(PRIME64_1);
+        while s.len() >= 32 {
+            v1 = round(v1, read_u64(&s[0..]));
+            v2 = round(v2, read_u64(&s[8..]));
+            v3 = round(v3, read_u64(&s[16..]));
+            v4 = round(v4, read_u64(&s[24..]));
+            s = &s[32..];
+        }
+        h = v1
+            .rotate_left(1)
+            .wrapping_add(v2.rotate_left(7))
+            .wrapping_add(v3.rotate_left(12))
+            .wrapping_add(v4.rotate_left(18));
+        h = merge_round(h, v1);
+        h = merge_round(h, v2);
+        h = merge_round(h, v3);
+        h = merge_round(h, v4);
+    } else {
+        h = PRIME64_5;
+    }
+    h = h.wrapping_add(len as u64);
+    while s.len() >= 8 {
+        h ^= round(0, read_u64(s));
+        h = h.rotate_left(27).wrapping_mul(PRIME64_1).wrapping_add(PRIME64_4);
+        s = &s[8..];
+    }
+    if s.len() >= 4 {
+        h ^= (read_u32(s) as u64).wrapping_mul(PRIME64_1);
+        h = h.rotate_left(23).wrapping_mul(PRIME64_2).wrapping_add(PRIME64_3);
+        s = &s[4..];
+    }
+    for b in s {
+        h ^= (*b as u64).wrapping_mul(PRIME64_5);
+        h = h.rotate_left(11).wrapping_mul(PRIME64_1);
+    }
+    h ^= h >> 33;
+    h = h.wrapping_mul(PRIME64_2);
+    h ^= h >> 29;
+    h = h.wrapping_mul(PRIME64_3);
+    h ^= h >> 32;
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xxhash64() {
+        assert_eq!(xxhash64(b""), 0xEF46DB3751D8E999);
+        assert_eq!(xxhash64(b"abc"), 0x44BC2CF5AD770999);
+        assert_eq!(xxhash64(b"Nobody inspects the spammish repetition"), 0xFBCEA83C8A378BF1);
+    }
+
+    #[cfg(feature = "xxhash")]
+    #[test]
+    fn test_xxhash64_range() {
+        use crate::Buffer;
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 7 + i / 13) as u8).collect();
+        let buf = Buffer::from(data.clone());
+        assert_eq!(buf.xxhash64_range(0..1000), buf.xxhash64());
+        for (start, end) in [(0, 0), (1, 2), (3, 35), (100, 1000), (999, 1000)] {
+            assert_eq!(buf.xxhash64_range(start..end), xxhash64(&data[start..end]));
+        }
+        assert_ne!(buf.xxhash64_range(0..999), buf.xxhash64());
+    }
+}